@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::process::Command;
 use serde::Deserialize;
 use crate::icon_map::get_icon;
@@ -15,6 +15,9 @@ pub struct WindowInfo {
     /// Sketchybar display ID (monitor-appkit-nsscreen-screens-id from aerospace)
     #[serde(rename = "monitor-appkit-nsscreen-screens-id")]
     pub display_id: u32,
+    /// Monitor name as reported by aerospace (e.g. "DELL U2720Q")
+    #[serde(default)]
+    pub monitor_name: String,
 }
 
 /// Information about a workspace
@@ -26,8 +29,11 @@ pub struct WorkspaceInfo {
     pub icons: String,
     #[allow(dead_code)] // Used in tests
     pub is_focused: bool,
-    /// Sketchybar display ID (directly from aerospace's monitor-appkit-nsscreen-screens-id)
+    /// Sketchybar display ID (from aerospace's monitor-appkit-nsscreen-screens-id,
+    /// after `apply_display_map`)
     pub display_id: u32,
+    /// Name of the monitor the workspace is on
+    pub monitor_name: String,
 }
 
 /// Get all windows using aerospace's JSON API
@@ -38,7 +44,7 @@ pub fn get_windows() -> Vec<WindowInfo> {
             "list-windows",
             "--all",
             "--format",
-            "%{app-name}%{workspace}%{workspace-is-focused}%{workspace-is-visible}%{monitor-appkit-nsscreen-screens-id}",
+            "%{app-name}%{workspace}%{workspace-is-focused}%{workspace-is-visible}%{monitor-appkit-nsscreen-screens-id}%{monitor-name}",
             "--json"
         ])
         .output()
@@ -67,6 +73,8 @@ pub struct FocusedWorkspaceInfo {
     pub workspace_is_visible: bool,
     #[serde(rename = "monitor-appkit-nsscreen-screens-id")]
     pub display_id: u32,
+    #[serde(default)]
+    pub monitor_name: String,
 }
 
 /// Get the currently focused workspace
@@ -77,7 +85,7 @@ pub fn get_focused_workspace() -> Option<FocusedWorkspaceInfo> {
             "list-workspaces",
             "--focused",
             "--format",
-            "%{workspace}%{workspace-is-focused}%{workspace-is-visible}%{monitor-appkit-nsscreen-screens-id}",
+            "%{workspace}%{workspace-is-focused}%{workspace-is-visible}%{monitor-appkit-nsscreen-screens-id}%{monitor-name}",
             "--json"
         ])
         .output()
@@ -92,6 +100,20 @@ pub fn get_focused_workspace() -> Option<FocusedWorkspaceInfo> {
     workspaces.into_iter().next()
 }
 
+/// Names of every workspace aerospace has, including those without windows
+pub fn list_workspaces() -> Vec<String> {
+    let output = match Command::new("aerospace").args(["list-workspaces", "--all"]).output() {
+        Ok(o) if o.status.success() => o,
+        _ => return Vec::new(),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|ws| !ws.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 /// Get workspace information for all workspaces
 ///
 /// # Arguments
@@ -136,12 +158,12 @@ pub fn get_workspace_infos(show_all_windows: bool) -> HashMap<String, WorkspaceI
     }
 
     // Group windows by workspace
-    let mut workspace_data: HashMap<String, (Vec<String>, bool, u32)> = HashMap::new();
+    let mut workspace_data: HashMap<String, (Vec<String>, bool, u32, String)> = HashMap::new();
 
     for window in windows {
         workspace_data
             .entry(window.workspace.clone())
-            .and_modify(|(apps, is_focused, _display_id)| {
+            .and_modify(|(apps, is_focused, _display_id, _monitor_name)| {
                 apps.push(window.app_name.clone());
                 *is_focused = *is_focused || window.workspace_is_focused;
             })
@@ -149,18 +171,19 @@ pub fn get_workspace_infos(show_all_windows: bool) -> HashMap<String, WorkspaceI
                 vec![window.app_name],
                 window.workspace_is_focused,
                 window.display_id,
+                window.monitor_name,
             ));
     }
 
     // If no workspace is marked as focused, query aerospace for the focused workspace
     // This handles the case where the focused workspace has no windows
-    let has_focused = workspace_data.values().any(|(_, is_focused, _)| *is_focused);
+    let has_focused = workspace_data.values().any(|(_, is_focused, _, _)| *is_focused);
     if !has_focused {
         if let Some(focused) = get_focused_workspace() {
             eprintln!("[AEROSPACE] No focused workspace in windows, adding empty focused workspace: {}", focused.workspace);
             workspace_data.insert(
                 focused.workspace.clone(),
-                (Vec::new(), true, focused.display_id),
+                (Vec::new(), true, focused.display_id, focused.monitor_name),
             );
         }
     }
@@ -168,13 +191,13 @@ pub fn get_workspace_infos(show_all_windows: bool) -> HashMap<String, WorkspaceI
     // Build workspace infos
     let mut result = HashMap::new();
 
-    for (ws_id, (apps, is_focused, display_id)) in workspace_data {
+    for (ws_id, (apps, is_focused, display_id, monitor_name)) in workspace_data {
         // Build icons string
         let icons: String = if show_all_windows {
             // Show an icon for each window
             apps
                 .iter()
-                .map(|app| format!("{}", get_icon(app)))
+                .map(|app| get_icon(app).to_string())
                 .collect()
         } else {
             // Show one icon per unique app
@@ -183,7 +206,7 @@ pub fn get_workspace_infos(show_all_windows: bool) -> HashMap<String, WorkspaceI
             unique_apps.dedup();
             unique_apps
                 .iter()
-                .map(|app| format!("{}", get_icon(app)))
+                .map(|app| get_icon(app).to_string())
                 .collect()
         };
 
@@ -195,6 +218,7 @@ pub fn get_workspace_infos(show_all_windows: bool) -> HashMap<String, WorkspaceI
                 icons: icons.trim_end().to_string(),
                 is_focused,
                 display_id,
+                monitor_name,
             },
         );
     }
@@ -202,6 +226,27 @@ pub fn get_workspace_infos(show_all_windows: bool) -> HashMap<String, WorkspaceI
     result
 }
 
+/// Remap each workspace's display ID using `display_map`.
+///
+/// Keys are either a monitor name as reported by aerospace or a numeric
+/// aerospace display ID; values are the sketchybar display ID to use. Name
+/// matches win over ID matches. Workspaces on monitors that aren't in the map
+/// keep their ID, and entries for monitors that aren't connected are ignored.
+pub fn apply_display_map(infos: &mut HashMap<String, WorkspaceInfo>, display_map: &BTreeMap<String, u32>) {
+    if display_map.is_empty() {
+        return;
+    }
+    for info in infos.values_mut() {
+        let mapped = display_map
+            .get(&info.monitor_name)
+            .filter(|_| !info.monitor_name.is_empty())
+            .or_else(|| display_map.get(&info.display_id.to_string()));
+        if let Some(display_id) = mapped {
+            info.display_id = *display_id;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -209,13 +254,69 @@ mod tests {
     #[test]
     fn test_workspace_infos_structure() {
         // This test verifies the structure without requiring aerospace
-        let mut info = WorkspaceInfo::default();
-        info.id = "1".to_string();
-        info.apps = vec!["Safari".to_string(), "Cursor".to_string()];
-        info.icons = ":safari: :cursor:".to_string();
-        info.is_focused = true;
+        let info = WorkspaceInfo {
+            id: "1".to_string(),
+            apps: vec!["Safari".to_string(), "Cursor".to_string()],
+            icons: ":safari: :cursor:".to_string(),
+            is_focused: true,
+            ..Default::default()
+        };
 
         assert_eq!(info.apps.len(), 2);
         assert!(info.is_focused);
     }
+
+    fn infos(monitors: &[(&str, u32, &str)]) -> HashMap<String, WorkspaceInfo> {
+        monitors
+            .iter()
+            .map(|(ws, display_id, monitor_name)| {
+                (
+                    ws.to_string(),
+                    WorkspaceInfo {
+                        id: ws.to_string(),
+                        display_id: *display_id,
+                        monitor_name: monitor_name.to_string(),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn map(entries: &[(&str, u32)]) -> BTreeMap<String, u32> {
+        entries.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    fn displays(infos: &HashMap<String, WorkspaceInfo>) -> BTreeMap<String, u32> {
+        infos.iter().map(|(k, v)| (k.clone(), v.display_id)).collect()
+    }
+
+    #[test]
+    fn test_display_map_identity() {
+        let mut ws = infos(&[("1", 1, "Built-in Retina Display"), ("2", 2, "DELL U2720Q")]);
+        apply_display_map(&mut ws, &BTreeMap::new());
+        assert_eq!(displays(&ws), map(&[("1", 1), ("2", 2)]));
+    }
+
+    #[test]
+    fn test_display_map_swap_by_id() {
+        let mut ws = infos(&[("1", 1, "A"), ("2", 2, "B"), ("3", 3, "C")]);
+        apply_display_map(&mut ws, &map(&[("2", 3), ("3", 2)]));
+        assert_eq!(displays(&ws), map(&[("1", 1), ("2", 3), ("3", 2)]));
+    }
+
+    #[test]
+    fn test_display_map_by_name_wins_over_id() {
+        let mut ws = infos(&[("1", 1, "Built-in Retina Display"), ("2", 2, "DELL U2720Q")]);
+        apply_display_map(&mut ws, &map(&[("DELL U2720Q", 3), ("2", 1)]));
+        assert_eq!(displays(&ws), map(&[("1", 1), ("2", 3)]));
+    }
+
+    #[test]
+    fn test_display_map_missing_monitor() {
+        // Entries for monitors that aren't connected don't affect anything
+        let mut ws = infos(&[("1", 1, "Built-in Retina Display"), ("2", 2, "")]);
+        apply_display_map(&mut ws, &map(&[("LG HDR 4K", 1), ("4", 2), ("", 5)]));
+        assert_eq!(displays(&ws), map(&[("1", 1), ("2", 2)]));
+    }
 }
@@ -4,6 +4,9 @@
 //! Config file: $XDG_CONFIG_HOME/aerospace/aerospace-workspaces.json
 //!              (or ~/.config/aerospace/aerospace-workspaces.json)
 //!
+//! An `aerospace-workspaces.toml` next to it takes precedence, with the same
+//! structure as `[[<workspace>]]` tables.
+//!
 //! Config structure:
 //! {
 //!   "<workspace>": [
@@ -20,6 +23,8 @@ use std::process::Command;
 
 use serde::{Deserialize, Serialize};
 
+use crate::config_format::{self, Format};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct WorkspaceConfig {
@@ -29,18 +34,22 @@ pub struct WorkspaceConfig {
 
 pub type AerospaceFocusConfig = HashMap<String, Vec<WorkspaceConfig>>;
 
-pub fn get_config_path() -> PathBuf {
+/// The config file, and any of another format it takes precedence over
+pub fn get_config_path() -> (PathBuf, Vec<PathBuf>) {
     let config_dir = env::var("XDG_CONFIG_HOME")
         .map(PathBuf::from)
         .unwrap_or_else(|_| {
             let home = env::var("HOME").expect("HOME not set");
             PathBuf::from(home).join(".config")
         });
-    config_dir.join("aerospace").join("aerospace-workspaces.json")
+    config_format::resolve(&config_dir.join("aerospace"), "aerospace-workspaces")
 }
 
 pub fn load_config() -> AerospaceFocusConfig {
-    let path = get_config_path();
+    let (path, ignored) = get_config_path();
+    for ignored in ignored {
+        eprintln!("[aerospace-focus] Ignoring {:?}, {:?} takes precedence", ignored, path);
+    }
 
     if !path.exists() {
         // Create an example config on first run
@@ -73,7 +82,10 @@ pub fn load_config() -> AerospaceFocusConfig {
         }
     };
 
-    match serde_json::from_str(&data) {
+    let parsed = Format::of(&path)
+        .and_then(|format| config_format::parse(&data, format))
+        .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()));
+    match parsed {
         Ok(cfg) => cfg,
         Err(e) => {
             eprintln!("[aerospace-focus] Failed to parse config {:?}: {}", path, e);
@@ -169,14 +181,3 @@ pub fn ensure_workspace_app(workspace: &str) {
         }
     }
 }
-
-/// Focus the workspace and launch the configured app if it isn't already running there.
-pub fn focus_workspace(workspace: &str) {
-    if !aerospace_focus(workspace) {
-        eprintln!(
-            "[aerospace-focus] Warning: aerospace workspace returned non-zero for '{}'",
-            workspace
-        );
-    }
-    ensure_workspace_app(workspace);
-}
@@ -0,0 +1,110 @@
+//! Low battery alerts
+//!
+//! Each alert fires once when the charge drops to its threshold. It can only
+//! fire again after the charge has climbed back above the threshold plus the
+//! hysteresis, so a battery hovering around 20% doesn't alert on every
+//! refresh. Nothing fires while on AC, but charging still re-arms alerts.
+
+use crate::config::BatteryConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Alert {
+    Warning,
+    Critical,
+}
+
+impl Alert {
+    pub fn name(self) -> &'static str {
+        match self {
+            Alert::Warning => "warning",
+            Alert::Critical => "critical",
+        }
+    }
+}
+
+/// Which alerts have fired and not been re-armed yet
+#[derive(Debug, Default)]
+pub struct BatteryAlerts {
+    /// The most severe alert that fired; the ones below it count as fired too
+    fired: Option<Alert>,
+}
+
+impl BatteryAlerts {
+    /// Take a new reading; returns the alert to raise, if any
+    pub fn update(&mut self, percentage: u8, on_ac: bool, config: &BatteryConfig) -> Option<Alert> {
+        let cleared = |threshold: u8| percentage > threshold.saturating_add(config.hysteresis);
+        if self.fired == Some(Alert::Critical) && cleared(config.critical) {
+            self.fired = Some(Alert::Warning);
+        }
+        if self.fired == Some(Alert::Warning) && cleared(config.warning) {
+            self.fired = None;
+        }
+        if on_ac {
+            return None;
+        }
+
+        let alert = if config.critical > 0 && percentage <= config.critical {
+            Alert::Critical
+        } else if config.warning > 0 && percentage <= config.warning {
+            Alert::Warning
+        } else {
+            return None;
+        };
+        if self.fired >= Some(alert) {
+            return None;
+        }
+        self.fired = Some(alert);
+        Some(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(readings: &[(u8, bool)]) -> Vec<Option<Alert>> {
+        let config = BatteryConfig::default();
+        let mut alerts = BatteryAlerts::default();
+        readings
+            .iter()
+            .map(|&(percentage, on_ac)| alerts.update(percentage, on_ac, &config))
+            .collect()
+    }
+
+    #[test]
+    fn test_alerts_fire_once_per_threshold() {
+        use Alert::*;
+        let readings = [(30, false), (20, false), (19, false), (12, false), (10, false), (4, false)];
+        assert_eq!(run(&readings), [None, Some(Warning), None, None, Some(Critical), None]);
+
+        // Straight past both thresholds: only the critical one
+        assert_eq!(run(&[(50, false), (8, false)]), [None, Some(Critical)]);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        use Alert::*;
+        // Hovering around the threshold doesn't re-arm, climbing past 25% does
+        let readings = [(20, false), (22, false), (20, false), (25, false), (20, false), (26, false), (20, false)];
+        assert_eq!(run(&readings), [Some(Warning), None, None, None, None, None, Some(Warning)]);
+
+        // Getting out of critical re-arms it, but not the warning
+        let readings = [(10, false), (16, false), (18, false), (10, false)];
+        assert_eq!(run(&readings), [Some(Critical), None, None, Some(Critical)]);
+    }
+
+    #[test]
+    fn test_no_alerts_on_ac() {
+        use Alert::*;
+        let readings = [(15, true), (9, true), (9, false), (30, true), (18, false)];
+        assert_eq!(run(&readings), [None, None, Some(Critical), None, Some(Warning)]);
+    }
+
+    #[test]
+    fn test_zero_turns_an_alert_off() {
+        let config = BatteryConfig { warning: 0, ..BatteryConfig::default() };
+        let mut alerts = BatteryAlerts::default();
+        assert_eq!(alerts.update(15, false, &config), None);
+        assert_eq!(alerts.update(5, false, &config), Some(Alert::Critical));
+    }
+}
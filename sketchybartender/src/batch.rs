@@ -0,0 +1,443 @@
+//! Batching of sketchybar commands
+//!
+//! `SketchybarBatch::execute` diffs every `--set` against the render cache and
+//! only sends properties whose value actually changed, so periodic refreshes
+//! that produce the same label cost no sketchybar traffic at all.
+
+use crate::render::{self, RenderCache};
+use crate::sink::{self, Delivery, Sink};
+
+/// A single sketchybar command queued in a batch
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchCommand {
+    Add { item: String, position: String },
+    Remove { item: String },
+    Set { item: String, props: Vec<(String, String)> },
+    Animate { curve: String, duration: u32 },
+    Reorder { items: Vec<String> },
+}
+
+/// A builder for batching sketchybar commands
+#[derive(Debug, Default)]
+pub struct SketchybarBatch {
+    commands: Vec<BatchCommand>,
+}
+
+impl SketchybarBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an item at `position` ("left", "right", "center", ...)
+    pub fn add(&mut self, item: &str, position: &str) -> &mut Self {
+        self.commands.push(BatchCommand::Add {
+            item: item.to_string(),
+            position: position.to_string(),
+        });
+        self
+    }
+
+    /// Remove an item (or every item matching a `/regex/`)
+    pub fn remove(&mut self, item: &str) -> &mut Self {
+        self.commands.push(BatchCommand::Remove {
+            item: item.to_string(),
+        });
+        self
+    }
+
+    /// Set properties on an item
+    pub fn set(&mut self, item: &str, props: &[(&str, &str)]) -> &mut Self {
+        self.commands.push(BatchCommand::Set {
+            item: item.to_string(),
+            props: props
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        });
+        self
+    }
+
+    /// Add animation with curve and duration
+    pub fn animate(&mut self, curve: &str, duration: u32) -> &mut Self {
+        self.commands.push(BatchCommand::Animate {
+            curve: curve.to_string(),
+            duration,
+        });
+        self
+    }
+
+    /// Reorder items so they appear on the bar in the given order
+    pub fn reorder(&mut self, items: &[&str]) -> &mut Self {
+        self.commands.push(BatchCommand::Reorder {
+            items: items.iter().map(|i| i.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Flatten the queued commands into sketchybar arguments
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for command in &self.commands {
+            match command {
+                BatchCommand::Add { item, position } => {
+                    args.push("--add".to_string());
+                    args.push("item".to_string());
+                    args.push(item.clone());
+                    args.push(position.clone());
+                }
+                BatchCommand::Remove { item } => {
+                    args.push("--remove".to_string());
+                    args.push(item.clone());
+                }
+                BatchCommand::Set { item, props } => {
+                    args.push("--set".to_string());
+                    args.push(item.clone());
+                    for (key, value) in props {
+                        args.push(format!("{}={}", key, value));
+                    }
+                }
+                BatchCommand::Animate { curve, duration } => {
+                    args.push("--animate".to_string());
+                    args.push(curve.clone());
+                    args.push(duration.to_string());
+                }
+                BatchCommand::Reorder { items } => {
+                    args.push("--reorder".to_string());
+                    args.extend(items.iter().cloned());
+                }
+            }
+        }
+        args
+    }
+
+    /// Execute the batched commands on the installed sink, skipping
+    /// properties that already hold the requested value
+    pub fn execute(&self) -> Result<(), std::io::Error> {
+        render::with_cache(|cache| self.send_cached(sink::current().as_ref(), cache))
+    }
+
+    /// Send only what differs from `cache`, then record the batch in it.
+    ///
+    /// If the sink had to reconnect, sketchybar has lost everything sent
+    /// before, so the full cached state is pushed again.
+    pub fn send_cached(&self, sink: &dyn Sink, cache: &mut RenderCache) -> Result<(), std::io::Error> {
+        let delivery = self.changed_since(cache).send_to(sink)?;
+        cache.record_batch(self);
+        if delivery == Delivery::Reconnected {
+            eprintln!("Sketchybar reconnected, re-pushing the full bar state");
+            cache.replay_batch().send_to(sink)?;
+        }
+        Ok(())
+    }
+
+    /// Execute the batched commands on a specific sink, bypassing the cache
+    pub fn send_to(&self, sink: &dyn Sink) -> Result<Delivery, std::io::Error> {
+        if self.commands.is_empty() {
+            return Ok(Delivery::Sent);
+        }
+
+        let args = self.args();
+
+        // Debug: print the command being sent
+        eprintln!("[DEBUG] Sending to sketchybar: {}", args.join(" "));
+
+        match sink.send(&args) {
+            Ok(delivery) => {
+                eprintln!("[DEBUG] Command sent successfully");
+                Ok(delivery)
+            }
+            Err(e) => {
+                eprintln!("[DEBUG] Command failed: {}", e);
+                Err(std::io::Error::other(e))
+            }
+        }
+    }
+
+    /// The part of this batch that would change what `cache` says is on the bar.
+    ///
+    /// Animated batches are returned unchanged: their intermediate values are
+    /// the whole point, even when the final value matches the cache. Removes
+    /// are always sent (the item may predate the cache), and an item removed
+    /// earlier in the batch starts over with no known values.
+    pub fn changed_since(&self, cache: &RenderCache) -> SketchybarBatch {
+        if self.commands.iter().any(|c| matches!(c, BatchCommand::Animate { .. })) {
+            return SketchybarBatch {
+                commands: self.commands.clone(),
+            };
+        }
+
+        // Values set earlier in this batch take precedence over the cache
+        let mut pending: Vec<(&str, &str, &str)> = Vec::new();
+        let mut removed: Vec<&str> = Vec::new();
+        let mut added: Vec<&str> = Vec::new();
+        let mut order = cache.order();
+        let mut commands = Vec::new();
+        for command in &self.commands {
+            match command {
+                BatchCommand::Reorder { items } => {
+                    if order != Some(items.as_slice()) {
+                        commands.push(command.clone());
+                        order = Some(items);
+                    }
+                }
+                BatchCommand::Remove { item } => {
+                    pending.retain(|(i, _, _)| i != item);
+                    added.retain(|i| i != item);
+                    removed.push(item);
+                    commands.push(command.clone());
+                }
+                BatchCommand::Add { item, .. } => {
+                    let exists = added.contains(&item.as_str())
+                        || (!removed.contains(&item.as_str()) && cache.is_added(item));
+                    if !exists {
+                        added.push(item);
+                        commands.push(command.clone());
+                    }
+                }
+                BatchCommand::Animate { .. } => {}
+                BatchCommand::Set { item, props } => {
+                    let mut changed = Vec::new();
+                    for (key, value) in props {
+                        let current = pending
+                            .iter()
+                            .rev()
+                            .find(|(i, k, _)| i == item && k == key)
+                            .map(|(_, _, v)| *v)
+                            .or_else(|| {
+                                if removed.contains(&item.as_str()) {
+                                    None
+                                } else {
+                                    cache.value(item, key)
+                                }
+                            });
+                        if current != Some(value.as_str()) {
+                            changed.push((key.clone(), value.clone()));
+                            pending.push((item, key, value));
+                        }
+                    }
+                    if !changed.is_empty() {
+                        commands.push(BatchCommand::Set {
+                            item: item.clone(),
+                            props: changed,
+                        });
+                    }
+                }
+            }
+        }
+        SketchybarBatch { commands }
+    }
+
+    pub fn commands(&self) -> &[BatchCommand] {
+        &self.commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn test_unchanged_values_are_not_resent() {
+        let sink = RecordingSink::new();
+        let mut cache = RenderCache::new();
+
+        let mut batch = SketchybarBatch::new();
+        batch.set("cpu", &[("label", "12%")]).set("ram", &[("label", "8.1/16GB")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch.set("cpu", &[("label", "13%")]).set("ram", &[("label", "8.1/16GB")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        assert_eq!(sink.batches(), vec![
+            vec!["--set", "cpu", "label=12%", "--set", "ram", "label=8.1/16GB"],
+            vec!["--set", "cpu", "label=13%"],
+        ]);
+    }
+
+    #[test]
+    fn test_only_changed_properties_are_sent() {
+        let sink = RecordingSink::new();
+        let mut cache = RenderCache::new();
+
+        let mut batch = SketchybarBatch::new();
+        batch.set("battery", &[("icon", "A"), ("label", "50%")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch.set("battery", &[("icon", "A"), ("label", "49%")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        assert_eq!(sink.batches()[1], vec!["--set", "battery", "label=49%"]);
+    }
+
+    #[test]
+    fn test_later_sets_in_a_batch_see_earlier_ones() {
+        let mut cache = RenderCache::new();
+        let mut batch = SketchybarBatch::new();
+        batch.set("workspace.1", &[("drawing", "off")]);
+        batch.send_cached(&RecordingSink::new(), &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch
+            .set("workspace.1", &[("drawing", "on")])
+            .set("workspace.1", &[("drawing", "off")]);
+        assert_eq!(batch.changed_since(&cache).args(), vec![
+            "--set", "workspace.1", "drawing=on",
+            "--set", "workspace.1", "drawing=off",
+        ]);
+    }
+
+    #[test]
+    fn test_animations_are_always_sent() {
+        let mut cache = RenderCache::new();
+        let mut batch = SketchybarBatch::new();
+        batch.set("teams", &[("icon.y_offset", "0")]);
+        batch.send_cached(&RecordingSink::new(), &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch
+            .animate("sin", 15)
+            .set("teams", &[("icon.y_offset", "-3")])
+            .animate("sin", 15)
+            .set("teams", &[("icon.y_offset", "0")]);
+        assert_eq!(batch.changed_since(&cache).args(), batch.args());
+    }
+
+    #[test]
+    fn test_reorder_is_sent_only_when_it_changes() {
+        let sink = RecordingSink::new();
+        let mut cache = RenderCache::new();
+
+        let mut batch = SketchybarBatch::new();
+        batch.reorder(&["workspace.1", "workspace.2", "workspace.10"]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch.reorder(&["workspace.2", "workspace.1", "workspace.10"]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        assert_eq!(sink.batches(), vec![
+            vec!["--reorder", "workspace.1", "workspace.2", "workspace.10"],
+            vec!["--reorder", "workspace.2", "workspace.1", "workspace.10"],
+        ]);
+        assert_eq!(cache.replay_batch().args(), vec![
+            "--reorder", "workspace.2", "workspace.1", "workspace.10",
+        ]);
+    }
+
+    #[test]
+    fn test_added_items_are_added_once() {
+        let sink = RecordingSink::new();
+        let mut cache = RenderCache::new();
+
+        let mut batch = SketchybarBatch::new();
+        batch.add("workspace.web", "left").set("workspace.web", &[("label", "web")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        assert_eq!(sink.batches(), vec![
+            vec!["--add", "item", "workspace.web", "left", "--set", "workspace.web", "label=web"],
+        ]);
+    }
+
+    #[test]
+    fn test_removed_items_start_over() {
+        let sink = RecordingSink::new();
+        let mut cache = RenderCache::new();
+
+        let mut batch = SketchybarBatch::new();
+        batch.add("workspace.web", "left").set("workspace.web", &[("label", "web")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch.remove("workspace.web");
+        batch.send_cached(&sink, &mut cache).unwrap();
+        assert!(cache.get("workspace.web").is_none());
+
+        // Re-adding sends everything again, even values the removed item had
+        let mut batch = SketchybarBatch::new();
+        batch
+            .remove("workspace.mail")
+            .add("workspace.web", "left")
+            .set("workspace.web", &[("label", "web")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        assert_eq!(sink.batches()[1..], vec![
+            vec!["--remove", "workspace.web"],
+            vec![
+                "--remove", "workspace.mail",
+                "--add", "item", "workspace.web", "left",
+                "--set", "workspace.web", "label=web",
+            ],
+        ]);
+    }
+
+    #[test]
+    fn test_remove_then_add_in_one_batch() {
+        let mut cache = RenderCache::new();
+        let mut batch = SketchybarBatch::new();
+        batch.add("workspace.1", "left").set("workspace.1", &[("drawing", "on")]);
+        batch.send_cached(&RecordingSink::new(), &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch
+            .remove("workspace.1")
+            .add("workspace.1", "left")
+            .set("workspace.1", &[("drawing", "on")]);
+        assert_eq!(batch.changed_since(&cache).args(), batch.args());
+    }
+
+    #[test]
+    fn test_reconnect_replays_full_state() {
+        /// Reports a reconnect on the given (0-based) send
+        struct ReconnectingSink {
+            inner: RecordingSink,
+            reconnect_on: usize,
+        }
+        impl Sink for ReconnectingSink {
+            fn send(&self, args: &[String]) -> Result<Delivery, String> {
+                let n = self.inner.batches().len();
+                self.inner.send(args)?;
+                Ok(if n == self.reconnect_on { Delivery::Reconnected } else { Delivery::Sent })
+            }
+        }
+
+        let sink = ReconnectingSink { inner: RecordingSink::new(), reconnect_on: 1 };
+        let mut cache = RenderCache::new();
+
+        let mut batch = SketchybarBatch::new();
+        batch.set("clock", &[("label", "12:00")]).set("cpu", &[("label", "3%")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        let mut batch = SketchybarBatch::new();
+        batch.set("clock", &[("label", "12:01")]);
+        batch.send_cached(&sink, &mut cache).unwrap();
+
+        assert_eq!(sink.inner.batches(), vec![
+            vec!["--set", "clock", "label=12:00", "--set", "cpu", "label=3%"],
+            vec!["--set", "clock", "label=12:01"],
+            vec!["--set", "clock", "label=12:01", "--set", "cpu", "label=3%"],
+        ]);
+    }
+
+    #[test]
+    fn test_failed_send_is_not_recorded() {
+        struct FailingSink;
+        impl Sink for FailingSink {
+            fn send(&self, _args: &[String]) -> Result<Delivery, String> {
+                Err("sketchybar is gone".to_string())
+            }
+        }
+
+        let mut cache = RenderCache::new();
+        let mut batch = SketchybarBatch::new();
+        batch.set("clock", &[("label", "12:00")]);
+        assert!(batch.send_cached(&FailingSink, &mut cache).is_err());
+        assert!(cache.value("clock", "label").is_none());
+    }
+}
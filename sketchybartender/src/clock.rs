@@ -0,0 +1,145 @@
+//! Injectable time source so timing logic can be tested without sleeping,
+//! and the wall-clock arithmetic for ticks that line up with the displayed time
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long after a minute (or second) boundary a tick fires, so the time
+/// read when rendering is safely past it
+const TICK_SLACK: Duration = Duration::from_millis(50);
+
+/// A source of monotonic and wall-clock time that can also block
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+    /// Wall-clock time. Unlike `now`, it keeps moving while the machine
+    /// sleeps, and it can jump.
+    fn wall(&self) -> SystemTime;
+}
+
+/// The real clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Time from `now` until just after the next whole minute (or second, if
+/// `seconds` is set), but no longer than `cap`.
+///
+/// Boundaries are computed on the Unix timestamp. Every time zone is offset
+/// from UTC by whole minutes, so they are the local minute boundaries too,
+/// DST changes included.
+pub fn until_next_tick(now: SystemTime, seconds: bool, cap: Duration) -> Duration {
+    let period = if seconds {
+        Duration::from_secs(1)
+    } else {
+        Duration::from_secs(60)
+    };
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let into_period = Duration::from_nanos((since_epoch.as_nanos() % period.as_nanos()) as u64);
+    let until = if into_period < TICK_SLACK {
+        // Just ticked; this boundary is done
+        period + TICK_SLACK - into_period
+    } else {
+        period - into_period + TICK_SLACK
+    };
+    until.min(cap)
+}
+
+/// A clock that only moves when told to; `sleep` advances it instantly
+#[cfg(test)]
+#[derive(Debug)]
+pub struct FakeClock {
+    now: std::sync::Mutex<(Instant, SystemTime)>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    /// Starts at 2023-11-14 22:13:20 UTC
+    pub fn new() -> Self {
+        Self::at(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    }
+
+    pub fn at(wall: SystemTime) -> Self {
+        Self {
+            now: std::sync::Mutex::new((Instant::now(), wall)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+
+    /// Move only the wall clock, as if the machine slept for `duration`
+    pub fn suspend(&self, duration: Duration) {
+        self.now.lock().unwrap().1 += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    fn wall(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn at(secs: u64, millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis)
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_ticks_align_to_the_minute() {
+        // 22:13:20.250 UTC
+        assert_eq!(until_next_tick(at(1_700_000_000, 250), false, HOUR), ms(39_800));
+        assert_eq!(until_next_tick(at(1_700_000_000, 250), true, HOUR), ms(800));
+        // Woken right on time: the next tick is a whole period away
+        assert_eq!(until_next_tick(at(1_700_000_040, 50), false, HOUR), ms(60_000));
+        assert_eq!(until_next_tick(at(1_700_000_039, 990), false, HOUR), ms(60));
+    }
+
+    #[test]
+    fn test_ticks_are_capped() {
+        assert_eq!(until_next_tick(at(1_700_000_000, 0), false, Duration::from_secs(15)), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn test_dst_change_keeps_minute_boundaries() {
+        // 2024-03-31 00:59:30 UTC: Europe/Berlin goes from 01:59:30 CET to
+        // 03:00 CEST in 30 seconds, which is still a minute boundary
+        assert_eq!(until_next_tick(at(1_711_846_770, 0), false, HOUR), ms(30_050));
+        // 03:00 CEST exactly: the next tick is 03:01
+        assert_eq!(until_next_tick(at(1_711_846_800, 0), false, HOUR), ms(60_050));
+    }
+}
@@ -0,0 +1,302 @@
+//! Trailing-edge coalescing of bursty events
+//!
+//! A burst of events (e.g. aerospace firing `move-node-to-workspace` followed
+//! by a focus change) should cause one refresh, and that refresh must run
+//! *after* the last event of the burst so the bar never shows stale state.
+//! `Coalescer` is the pure state machine; `CoalescingRunner` drives it on a
+//! background thread.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// What the worker should do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Poll {
+    /// Nothing pending
+    Idle,
+    /// An event is pending but the window hasn't closed yet
+    Wait(Duration),
+    /// The window after the last event has passed; run now
+    Run,
+}
+
+/// Tracks pending events and decides when the coalesced run is due
+#[derive(Debug, Clone)]
+pub struct Coalescer {
+    window: Duration,
+    last_event: Option<Instant>,
+    pending: bool,
+}
+
+impl Coalescer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_event: None,
+            pending: false,
+        }
+    }
+
+    pub fn last_event(&self) -> Option<Instant> {
+        self.last_event
+    }
+
+    /// Change the quiet period; applies to the pending run too
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Record an event at `now`; pushes the run out to `now + window`
+    pub fn event(&mut self, now: Instant) {
+        self.last_event = Some(now);
+        self.pending = true;
+    }
+
+    /// Decide what to do at `now`. Returning `Run` clears the pending flag, so
+    /// any event arriving while the run is in progress schedules another one.
+    pub fn poll(&mut self, now: Instant) -> Poll {
+        let last_event = match (self.pending, self.last_event) {
+            (true, Some(t)) => t,
+            _ => return Poll::Idle,
+        };
+        let due = last_event + self.window;
+        if now >= due {
+            self.pending = false;
+            Poll::Run
+        } else {
+            Poll::Wait(due - now)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RunnerState {
+    coalescer: Coalescer,
+    /// Whether a worker thread is currently driving the coalescer
+    worker_active: bool,
+}
+
+/// Runs a job once per burst of `trigger` calls, after the burst settles
+#[derive(Debug)]
+pub struct CoalescingRunner {
+    clock: Arc<dyn Clock>,
+    state: Mutex<RunnerState>,
+}
+
+impl CoalescingRunner {
+    pub fn new(window: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            state: Mutex::new(RunnerState {
+                coalescer: Coalescer::new(window),
+                worker_active: false,
+            }),
+        }
+    }
+
+    /// When the last event arrived
+    pub fn last_event(&self) -> Option<Instant> {
+        self.lock().coalescer.last_event()
+    }
+
+    /// Change the quiet period (e.g. after the config was reloaded)
+    pub fn set_window(&self, window: Duration) {
+        self.lock().coalescer.set_window(window);
+    }
+
+    /// Record an event; `job` runs on a background thread once no further
+    /// event has arrived for the configured window
+    pub fn trigger(self: &Arc<Self>, job: impl Fn() + Send + 'static) {
+        if self.record_event() {
+            let runner = Arc::clone(self);
+            thread::spawn(move || runner.drain(job));
+        }
+    }
+
+    /// Record an event; returns true if the caller must start a worker
+    fn record_event(&self) -> bool {
+        let mut state = self.lock();
+        state.coalescer.event(self.clock.now());
+        !std::mem::replace(&mut state.worker_active, true)
+    }
+
+    /// Worker loop: wait for the window to close, run, repeat while events
+    /// keep arriving, then exit
+    fn drain(&self, job: impl Fn()) {
+        loop {
+            let poll = {
+                let mut state = self.lock();
+                let poll = state.coalescer.poll(self.clock.now());
+                if poll == Poll::Idle {
+                    state.worker_active = false;
+                }
+                poll
+            };
+            match poll {
+                Poll::Idle => return,
+                Poll::Wait(duration) => self.clock.sleep(duration),
+                Poll::Run => job(),
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RunnerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use proptest::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_single_event_runs_after_window() {
+        let t0 = Instant::now();
+        let mut c = Coalescer::new(WINDOW);
+        assert_eq!(c.poll(t0), Poll::Idle);
+
+        c.event(t0);
+        assert_eq!(c.poll(t0 + ms(40)), Poll::Wait(ms(60)));
+        assert_eq!(c.poll(t0 + ms(100)), Poll::Run);
+        assert_eq!(c.poll(t0 + ms(200)), Poll::Idle);
+    }
+
+    #[test]
+    fn test_burst_runs_once_after_last_event() {
+        let t0 = Instant::now();
+        let mut c = Coalescer::new(WINDOW);
+        c.event(t0);
+        c.event(t0 + ms(60));
+        c.event(t0 + ms(120));
+
+        assert_eq!(c.poll(t0 + ms(130)), Poll::Wait(ms(90)));
+        assert_eq!(c.poll(t0 + ms(220)), Poll::Run);
+        assert_eq!(c.poll(t0 + ms(220)), Poll::Idle);
+    }
+
+    #[test]
+    fn test_event_during_run_schedules_another() {
+        let t0 = Instant::now();
+        let mut c = Coalescer::new(WINDOW);
+        c.event(t0);
+        assert_eq!(c.poll(t0 + ms(100)), Poll::Run);
+
+        // Arrives while the refresh is still running
+        c.event(t0 + ms(105));
+        assert_eq!(c.poll(t0 + ms(110)), Poll::Wait(ms(95)));
+        assert_eq!(c.poll(t0 + ms(205)), Poll::Run);
+    }
+
+    #[test]
+    fn test_set_window_applies_to_pending_run() {
+        let t0 = Instant::now();
+        let mut c = Coalescer::new(WINDOW);
+        c.event(t0);
+        c.set_window(ms(20));
+        assert_eq!(c.poll(t0 + ms(10)), Poll::Wait(ms(10)));
+        assert_eq!(c.poll(t0 + ms(20)), Poll::Run);
+    }
+
+    #[test]
+    fn test_runner_runs_once_per_burst() {
+        let clock = Arc::new(FakeClock::new());
+        let runner = CoalescingRunner::new(WINDOW, clock.clone());
+        let start = clock.now();
+
+        // Three events before any worker gets to run: only the first needs one
+        assert!(runner.record_event());
+        clock.advance(ms(30));
+        assert!(!runner.record_event());
+        clock.advance(ms(30));
+        assert!(!runner.record_event());
+
+        let runs = AtomicUsize::new(0);
+        runner.drain(|| {
+            runs.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        // The run happened one window after the last event
+        assert_eq!(clock.now() - start, ms(160));
+        // The worker has exited, so the next event starts a new one
+        assert!(runner.record_event());
+    }
+
+    /// Replays a burst against the state machine the way the worker thread
+    /// would drive it, rendering the "world" whenever it runs. Returns the
+    /// value rendered last and the number of runs.
+    fn simulate(gaps: &[u64], window: u64, run_cost: u64) -> (Option<usize>, usize) {
+        let t0 = Instant::now();
+        let mut c = Coalescer::new(ms(window));
+        let mut events = Vec::new();
+        let mut t = t0;
+        for gap in gaps {
+            t += ms(*gap);
+            events.push(t);
+        }
+
+        let mut world = None;
+        let mut rendered = None;
+        let mut runs = 0;
+        let mut worker_wake: Option<Instant> = None;
+        let mut next_event = 0;
+
+        loop {
+            let event_at = events.get(next_event).copied();
+            match (event_at, worker_wake) {
+                (None, None) => break,
+                // Events win ties: they're applied before the worker looks
+                (Some(e), w) if w.is_none_or(|w| e <= w) => {
+                    world = Some(next_event);
+                    c.event(e);
+                    if worker_wake.is_none() {
+                        worker_wake = Some(e);
+                    }
+                    next_event += 1;
+                }
+                (_, Some(w)) => match c.poll(w) {
+                    Poll::Idle => worker_wake = None,
+                    Poll::Wait(d) => worker_wake = Some(w + d),
+                    Poll::Run => {
+                        rendered = world;
+                        runs += 1;
+                        worker_wake = Some(w + ms(run_cost));
+                    }
+                },
+                (Some(_), None) => unreachable!(),
+            }
+        }
+        (rendered, runs)
+    }
+
+    #[test]
+    fn test_move_then_focus_renders_final_state() {
+        // move-node-to-workspace followed 20ms later by the focus change
+        assert_eq!(simulate(&[0, 20], 100, 30), (Some(1), 1));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_final_state_is_always_rendered(
+            gaps in proptest::collection::vec(0u64..300, 1..20),
+            window in 1u64..200,
+            run_cost in 0u64..150,
+        ) {
+            let (rendered, runs) = simulate(&gaps, window, run_cost);
+            prop_assert_eq!(rendered, Some(gaps.len() - 1));
+            prop_assert!(runs <= gaps.len());
+        }
+    }
+}
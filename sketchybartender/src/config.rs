@@ -1,25 +1,208 @@
 //! Configuration module for sketchybartender update intervals
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::Locale;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-/// Configuration for update intervals (in seconds)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    /// Clock update interval (default: 15 seconds)
-    pub clock_interval: u64,
+use crate::config_format::{self, Format};
+use crate::layers::{self, Merged, Source};
+use crate::migration;
+use crate::icons::IconSet;
+use crate::sink::SinkKind;
+
+/// Interval overrides for one power source; unset ones keep the base value
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntervalOverrides {
+    pub clock: Option<u64>,
+    pub battery: Option<u64>,
+    pub brew: Option<u64>,
+    pub teams: Option<u64>,
+    pub system: Option<u64>,
+}
+
+/// Whether the machine runs on mains power or on its battery
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerSource {
+    #[default]
+    Ac,
+    Battery,
+}
+
+/// Update intervals (in seconds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Intervals {
+    /// Longest wait between clock updates; the clock also updates on every
+    /// minute (default: 15 seconds)
+    pub clock: u64,
     /// Battery update interval (default: 120 seconds)
-    pub battery_interval: u64,
+    pub battery: u64,
     /// Brew outdated check interval (default: 3600 seconds / 1 hour)
-    pub brew_interval: u64,
+    pub brew: u64,
     /// Teams notification check interval (default: 30 seconds)
-    pub teams_interval: u64,
+    pub teams: u64,
     /// System (CPU/RAM) update interval (default: 5 seconds)
-    pub system_interval: u64,
-    /// Workspace background color (default: 0xfff38ba8)
-    pub workspace_bg_color: String,
+    pub system: u64,
+    /// Overrides while on AC power (default: none)
+    pub on_ac: IntervalOverrides,
+    /// Overrides while on battery (default: brew every 4 hours, system every
+    /// 30 seconds)
+    pub on_battery: IntervalOverrides,
+}
+
+impl Default for Intervals {
+    fn default() -> Self {
+        Self {
+            clock: 15,
+            battery: 120,
+            brew: 3600,
+            teams: 30,
+            system: 5,
+            on_ac: IntervalOverrides::default(),
+            on_battery: IntervalOverrides {
+                brew: Some(4 * 3600),
+                system: Some(30),
+                ..IntervalOverrides::default()
+            },
+        }
+    }
+}
+
+impl Intervals {
+    /// The intervals to use on `power`, with that profile's overrides applied
+    pub fn on(&self, power: PowerSource) -> Intervals {
+        let overrides = match power {
+            PowerSource::Ac => &self.on_ac,
+            PowerSource::Battery => &self.on_battery,
+        };
+        Intervals {
+            clock: overrides.clock.unwrap_or(self.clock),
+            battery: overrides.battery.unwrap_or(self.battery),
+            brew: overrides.brew.unwrap_or(self.brew),
+            teams: overrides.teams.unwrap_or(self.teams),
+            system: overrides.system.unwrap_or(self.system),
+            ..self.clone()
+        }
+    }
+}
+
+/// Where extra text (clock time zones, battery time remaining) is shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// After the main text in the item's label
+    #[default]
+    Label,
+    /// One line each in the item's popup
+    Popup,
+}
+
+/// An extra time zone shown by the clock
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockZone {
+    /// Shown before the time, e.g. "NYC"
+    pub label: String,
+    /// IANA time zone name, e.g. "America/New_York"
+    pub tz: String,
+}
+
+/// Clock item settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    /// strftime format of the local time; `%V` is the ISO week and `%j` the
+    /// day of the year (default: "%d/%m %H:%M")
+    pub format: String,
+    /// Locale for weekday and month names, e.g. "de_DE" (default: en_US)
+    pub locale: String,
+    /// Extra time zones (default: none)
+    pub zones: Vec<ClockZone>,
+    /// strftime format of the extra zones' times (default: "%H:%M")
+    pub zone_format: String,
+    /// Where the extra zones go: "label" or "popup" (default: label)
+    pub zones_in: Placement,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            format: "%d/%m %H:%M".to_string(),
+            locale: "en_US".to_string(),
+            zones: Vec::new(),
+            zone_format: "%H:%M".to_string(),
+            zones_in: Placement::default(),
+        }
+    }
+}
+
+/// Battery item settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatteryConfig {
+    /// Where the time until empty or full goes: "label" or "popup"
+    /// (default: label)
+    pub time_in: Placement,
+    /// Battery levels to tell apart, at least 2; more than the icon set has
+    /// are capped (default: all of the set's)
+    pub steps: Option<usize>,
+    /// Charge (%) at or below which the battery flashes once; 0 to turn off
+    /// (default: 20)
+    pub warning: u8,
+    /// Charge (%) at or below which the battery flashes again and turns
+    /// `battery_low_color`; 0 to turn off (default: 10)
+    pub critical: u8,
+    /// How far (%) above a threshold the charge has to get before that
+    /// alert can fire again (default: 5)
+    pub hysteresis: u8,
+    /// Color the battery flashes on a warning (default: 0xfffe8019)
+    pub warning_color: String,
+    /// Shell command run on every alert, with $SKETCHYBARTENDER_ALERT set to
+    /// "warning" or "critical" and $SKETCHYBARTENDER_BATTERY to the charge;
+    /// empty for none (default: empty)
+    pub alert_command: String,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            time_in: Placement::default(),
+            steps: None,
+            warning: 20,
+            critical: 10,
+            hysteresis: 5,
+            warning_color: "0xfffe8019".to_string(),
+            alert_command: String::new(),
+        }
+    }
+}
+
+/// Daemon configuration
+///
+/// Every field is optional in the config file; missing ones take their
+/// default value. Files written for an older `version` are migrated when
+/// loaded (see `migration`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Schema version of the file (default: the current version)
+    pub version: u32,
+    /// Update intervals
+    pub intervals: Intervals,
+    /// Clock format, locale and extra time zones
+    pub clock: ClockConfig,
+    /// Battery time remaining, icon levels and low battery alerts
+    pub battery: BatteryConfig,
+    /// Battery and volume glyphs: "nerd-font", "sf-symbols" or "text"
+    /// (default: nerd-font)
+    pub icons: IconSet,
     /// Workspace focused label color (default: 0xff1d2021)
     pub workspace_focused_label_color: String,
     /// Workspace focused icon color (default: 0xff1d2021)
@@ -42,17 +225,56 @@ pub struct Config {
     pub battery_low_color: String,
     /// Battery color when discharging normally (default: 0xffF5EEE2)
     pub battery_normal_color: String,
+    /// Where bar updates are sent: "mach", "cli" or "stdout" (default: mach)
+    pub sink: SinkKind,
+    /// Quiet period after the last workspace event before refreshing (default: 100 ms)
+    pub workspace_coalesce_ms: u64,
+    /// Maps aerospace monitors to sketchybar display IDs. Keys are a monitor
+    /// name (e.g. "DELL U2720Q") or an aerospace display ID (e.g. "2");
+    /// unmapped monitors keep their ID (default: empty)
+    pub display_map: BTreeMap<String, u32>,
+    /// Workspaces to show first, in this order; the rest follow in natural
+    /// order, so "2" comes before "10" (default: empty)
+    pub workspace_order: Vec<String>,
+    /// Properties set on workspace items when the daemon adds them. `{workspace}`
+    /// is replaced with the workspace name (default: fonts, padding and corner
+    /// radius matching the stock sketchybarrc)
+    pub workspace_style: BTreeMap<String, String>,
+    /// Item the workspace items are kept in front of on the bar; empty to
+    /// leave them wherever sketchybar adds them (default: front_app)
+    pub workspace_anchor: String,
+}
+
+fn default_workspace_style() -> BTreeMap<String, String> {
+    [
+        ("icon.font", "sketchybar-app-font:Regular:13.0"),
+        ("label.font", "JetbrainsMono Nerd Font:Bold:13.0"),
+        ("background.corner_radius", "10"),
+        ("background.height", "22"),
+        ("background.drawing", "off"),
+        ("icon.padding_left", "0"),
+        ("icon.padding_right", "0"),
+        ("label.padding_left", "1"),
+        ("label.padding_right", "2"),
+        ("label.y_offset", "0"),
+        ("padding_left", "0"),
+        ("padding_right", "11"),
+        ("drawing", "off"),
+        ("click_script", "aerospace workspace {workspace}"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect()
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            clock_interval: 15,
-            battery_interval: 120,
-            brew_interval: 3600,
-            teams_interval: 30,
-            system_interval: 5,
-            workspace_bg_color: "0xffbb60cd".to_string(),
+            version: migration::CURRENT_VERSION,
+            intervals: Intervals::default(),
+            clock: ClockConfig::default(),
+            battery: BatteryConfig::default(),
+            icons: IconSet::default(),
             workspace_focused_label_color: "0xff1d2021".to_string(),
             workspace_focused_icon_color: "0xff1d2021".to_string(),
             workspace_unfocused_label_color: "0xffF5EEE2".to_string(),
@@ -64,55 +286,430 @@ impl Default for Config {
             battery_charging_color: "0xfffabd2f".to_string(),
             battery_low_color: "0xfffb4934".to_string(),
             battery_normal_color: "0xffF5EEE2".to_string(),
+            sink: SinkKind::default(),
+            workspace_coalesce_ms: 100,
+            display_map: BTreeMap::new(),
+            workspace_order: Vec::new(),
+            workspace_style: default_workspace_style(),
+            workspace_anchor: "front_app".to_string(),
         }
     }
 }
 
 impl Config {
-    /// Load configuration from file or use defaults
+    /// This config with `intervals` set to the ones for `power`
+    pub fn for_power(&self, power: PowerSource) -> Self {
+        Self {
+            intervals: self.intervals.on(power),
+            ..self.clone()
+        }
+    }
+
+    /// Load the configuration from every layer (see `layers`), creating the
+    /// user file if there is none.
+    ///
+    /// Invalid values are reported and replaced by their defaults; a file is
+    /// only ignored if it can't be parsed at all.
     pub fn load() -> Self {
         let config_path = Self::get_config_path();
 
-        if config_path.exists() {
-            match Self::load_from_file(&config_path) {
-                Ok(config) => config,
-                Err(e) => {
-                    eprintln!("Failed to load config from {:?}: {}", config_path, e);
-                    eprintln!("Using default configuration");
-                    Self::default()
-                }
-            }
-        } else {
+        if !config_path.exists() {
             // Create default config file
-            let config = Self::default();
-            if let Err(e) = config.save_to_file(&config_path) {
+            if let Err(e) = Self::default().save_to_file(&config_path) {
                 eprintln!("Failed to save default config: {}", e);
             } else {
                 eprintln!("Created default config at {:?}", config_path);
             }
-            config
+        }
+
+        let (check, _) = Self::check_layers();
+        if check.from_version < migration::CURRENT_VERSION {
+            eprintln!(
+                "Config is version {}, migrated in memory (run `sketchybartender --migrate-config [FILE]` to update the file):",
+                check.from_version
+            );
+            for note in &check.migration_notes {
+                eprintln!("  {}", note);
+            }
+        }
+        for warning in &check.warnings {
+            eprintln!("Config warning: {}", warning);
+        }
+        for error in &check.errors {
+            eprintln!("Config error: {}", error);
+        }
+        match check.config {
+            Some(config) => config.with_defaults_for(&check.errors),
+            None => {
+                eprintln!("Using default configuration");
+                Self::default()
+            }
+        }
+    }
+
+    /// Load the configuration again, failing instead of falling back to
+    /// defaults so a broken edit doesn't reset a running daemon
+    pub fn reload() -> Result<Self, String> {
+        let config_path = Self::get_config_path();
+        if !config_path.exists() {
+            return Err(format!("Config file {:?} does not exist", config_path));
+        }
+        let (check, _) = Self::check_layers();
+        for warning in &check.warnings {
+            eprintln!("Config warning: {}", warning);
+        }
+        match check.config {
+            Some(config) if check.errors.is_empty() => Ok(config),
+            _ => Err(check
+                .errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")),
         }
     }
 
-    /// Get the configuration file path
-    fn get_config_path() -> PathBuf {
+    fn config_dir() -> PathBuf {
         let config_dir = env::var("XDG_CONFIG_HOME")
             .map(PathBuf::from)
             .unwrap_or_else(|_| {
                 let home = env::var("HOME").expect("HOME not set");
                 PathBuf::from(home).join(".config")
             });
+        config_dir.join("sketchybar")
+    }
 
-        config_dir.join("sketchybar").join("sketchybartender.json")
+    /// Get the configuration file path: `sketchybartender.toml` if it exists,
+    /// otherwise `sketchybartender.json`
+    pub fn get_config_path() -> PathBuf {
+        config_format::resolve(&Self::config_dir(), "sketchybartender").0
     }
 
-    /// Load configuration from a file
-    fn load_from_file(path: &PathBuf) -> Result<Self, String> {
+    /// Files merged over the defaults, lowest precedence first: the user
+    /// file, then the one for this host. Either may not exist.
+    pub fn layer_paths() -> Vec<PathBuf> {
+        Self::resolve_layers().into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// Each layer file with the files of other formats it takes precedence over
+    fn resolve_layers() -> Vec<(PathBuf, Vec<PathBuf>)> {
+        let mut names = vec!["sketchybartender".to_string()];
+        if let Some(host) = layers::hostname() {
+            names.push(format!("sketchybartender.{}", host));
+        }
+        names
+            .iter()
+            .map(|name| config_format::resolve(&Self::config_dir(), name))
+            .collect()
+    }
+
+    /// Merge the defaults, the layer files and the environment, and check
+    /// the result. Problems are tagged with the layer they came from.
+    pub fn check_layers() -> (ConfigCheck, Merged) {
+        let layers = Self::resolve_layers();
+        let paths: Vec<PathBuf> = layers.iter().map(|(path, _)| path.clone()).collect();
+        let (mut check, merged) = Self::check_layers_from(&paths, env::vars());
+        for (path, ignored) in layers {
+            for ignored in ignored {
+                check.warnings.push(Issue::new(
+                    "",
+                    format!("ignoring {:?}, {:?} takes precedence", ignored, path),
+                ));
+            }
+        }
+        (check, merged)
+    }
+
+    fn check_layers_from(
+        paths: &[PathBuf],
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> (ConfigCheck, Merged) {
+        let mut check = ConfigCheck {
+            from_version: migration::CURRENT_VERSION,
+            ..ConfigCheck::default()
+        };
+        let defaults = serde_json::to_value(Self::default()).unwrap_or_default();
+        let mut merged = Merged::new(defaults.clone());
+
+        for path in paths.iter().filter(|p| p.exists()) {
+            let source = Source::File(path.clone());
+            let mut layer = ConfigCheck::default();
+            let value = match Self::check_file_layer(path, &mut layer) {
+                Ok(value) => value,
+                Err(e) => {
+                    layer.errors.push(Issue::new("", e));
+                    None
+                }
+            };
+            if let Some(value) = value {
+                merged.apply(&value, &source);
+                check.from_version = check.from_version.min(layer.from_version);
+            }
+            check.errors.extend(layer.errors.into_iter().map(|i| i.from(&source)));
+            check.warnings.extend(layer.warnings.into_iter().map(|i| i.from(&source)));
+            check
+                .migration_notes
+                .extend(layer.migration_notes.into_iter().map(|n| format!("{} (in {})", n, source)));
+        }
+
+        for (name, value) in layers::env_layers(vars) {
+            let source = Source::Env(name);
+            let mut warnings = Vec::new();
+            if let (Some(object), Some(known)) = (value.as_object(), defaults.as_object()) {
+                unknown_keys(object, known, "", &mut warnings);
+            }
+            check.warnings.extend(warnings.into_iter().map(|i| i.from(&source)));
+            merged.apply(&value, &source);
+        }
+
+        let mut result = ConfigCheck::default();
+        Self::deserialize_checked(merged.value.clone(), &mut result);
+        check.config = result.config;
+        check
+            .errors
+            .extend(result.errors.into_iter().map(|i| match merged.source_of(&i.path) {
+                Source::Default => i,
+                source => i.from(source),
+            }));
+        (check, merged)
+    }
+
+    /// Read, parse and migrate one layer file
+    fn check_file_layer(path: &Path, check: &mut ConfigCheck) -> Result<Option<Value>, String> {
+        let format = Format::of(path)?;
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        Ok(Self::read_layer(&contents, format, check))
+    }
+
+    /// Upgrade the config file at `path` to the current version in place,
+    /// keeping the original next to it as `<name>.v<version>.bak`.
+    ///
+    /// Only keys present in the file are written back. Returns the version
+    /// migrated from, the backup path and what changed, or `None` if the file
+    /// was already current.
+    pub fn migrate_file(path: &Path) -> Result<Option<(u32, PathBuf, Vec<String>)>, String> {
+        let format = Format::of(path)?;
         let contents = fs::read_to_string(path)
             .map_err(|e| format!("Failed to read file: {}", e))?;
+        let value = config_format::parse(&contents, format)?;
+        let migrated = migration::migrate(value)?;
+        if migrated.from_version == migration::CURRENT_VERSION {
+            return Ok(None);
+        }
 
-        serde_json::from_str(&contents)
-            .map_err(|e| format!("Failed to parse JSON config: {}", e))
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".v{}.bak", migrated.from_version));
+        let backup = PathBuf::from(backup);
+        fs::copy(path, &backup)
+            .map_err(|e| format!("Failed to back up config to {:?}: {}", backup, e))?;
+
+        let contents = config_format::serialize(&migrated.value, format)?;
+        fs::write(path, contents)
+            .map_err(|e| format!("Failed to write config file: {}", e))?;
+
+        Ok(Some((migrated.from_version, backup, migrated.notes)))
+    }
+
+    /// Read and check a config file (TOML or JSON, by extension)
+    pub fn check_file(path: &Path) -> Result<ConfigCheck, String> {
+        let format = Format::of(path)?;
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        Ok(Self::check_str(&contents, format))
+    }
+
+    /// Parse `contents` and report every problem found, with the key it is at
+    /// (merged over the defaults the same way `check_layers` does)
+    pub fn check_str(contents: &str, format: Format) -> ConfigCheck {
+        let mut check = ConfigCheck::default();
+        if let Some(value) = Self::read_layer(contents, format, &mut check) {
+            let mut merged = Merged::new(serde_json::to_value(Self::default()).unwrap_or_default());
+            merged.apply(&value, &Source::Default);
+            Self::deserialize_checked(merged.value, &mut check);
+        }
+        check
+    }
+
+    /// Parse and migrate one file's contents, warning about unknown keys
+    fn read_layer(contents: &str, format: Format, check: &mut ConfigCheck) -> Option<Value> {
+        let value = match config_format::parse(contents, format) {
+            Ok(value) => value,
+            Err(e) => {
+                check.errors.push(Issue::new("", e));
+                return None;
+            }
+        };
+        if !value.is_object() {
+            check.errors.push(Issue::new("", format!("expected a {} object", format)));
+            return None;
+        }
+
+        let value = match migration::migrate(value) {
+            Ok(migrated) => {
+                check.from_version = migrated.from_version;
+                check.migration_notes = migrated.notes;
+                migrated.value
+            }
+            Err(e) => {
+                check.errors.push(Issue::new("version", e));
+                return None;
+            }
+        };
+
+        if let (Some(object), Ok(Value::Object(known))) = (value.as_object(), serde_json::to_value(Self::default())) {
+            unknown_keys(object, &known, "", &mut check.warnings);
+        }
+        Some(value)
+    }
+
+    /// Deserialize a merged or single-file value and validate the result.
+    /// A value of the wrong type is reported and reset to its default, then
+    /// the rest is deserialized again, so one bad key doesn't cost the others.
+    fn deserialize_checked(mut value: Value, check: &mut ConfigCheck) {
+        let defaults = serde_json::to_value(Self::default()).unwrap_or_default();
+        loop {
+            match serde_path_to_error::deserialize::<_, Config>(value.clone()) {
+                Ok(config) => {
+                    check.errors.extend(config.validate());
+                    check.config = Some(config);
+                    return;
+                }
+                Err(e) => {
+                    let path = e.path().to_string();
+                    let path = if path == "." { String::new() } else { path };
+                    check.errors.push(Issue::new(&path, e.into_inner().to_string()));
+                    // Give up if the defaults themselves don't deserialize
+                    if value == defaults || !reset_to_default(&mut value, &defaults, &path) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reset every top-level key mentioned in `issues` to its default
+    pub fn with_defaults_for(self, issues: &[Issue]) -> Self {
+        if issues.is_empty() {
+            return self;
+        }
+        let (Ok(mut value), Ok(defaults)) = (
+            serde_json::to_value(&self),
+            serde_json::to_value(Self::default()),
+        ) else {
+            return self;
+        };
+        for issue in issues {
+            reset_to_default(&mut value, &defaults, &issue.path);
+        }
+        serde_json::from_value(value).unwrap_or(self)
+    }
+
+    /// Check values that parse fine but can't work
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let intervals = [
+            ("intervals.clock", self.intervals.clock),
+            ("intervals.battery", self.intervals.battery),
+            ("intervals.brew", self.intervals.brew),
+            ("intervals.teams", self.intervals.teams),
+            ("intervals.system", self.intervals.system),
+        ];
+        for (key, secs) in intervals {
+            if secs == 0 {
+                issues.push(Issue::new(key, "must be at least 1 second"));
+            }
+        }
+        for (profile, overrides) in [("on_ac", &self.intervals.on_ac), ("on_battery", &self.intervals.on_battery)] {
+            let overrides = [
+                ("clock", overrides.clock),
+                ("battery", overrides.battery),
+                ("brew", overrides.brew),
+                ("teams", overrides.teams),
+                ("system", overrides.system),
+            ];
+            for (key, secs) in overrides {
+                if secs == Some(0) {
+                    issues.push(Issue::new(&format!("intervals.{}.{}", profile, key), "must be at least 1 second"));
+                }
+            }
+        }
+
+        if self.battery.steps.is_some_and(|steps| steps < 2) {
+            issues.push(Issue::new("battery.steps", "must be at least 2"));
+        }
+        for (key, percent) in [("battery.warning", self.battery.warning), ("battery.critical", self.battery.critical)] {
+            if percent > 100 {
+                issues.push(Issue::new(key, "must be a percentage from 0 to 100"));
+            }
+        }
+        if self.battery.critical > self.battery.warning && self.battery.warning > 0 {
+            issues.push(Issue::new("battery.critical", "must not be above battery.warning"));
+        }
+
+        let colors = [
+            ("workspace_focused_label_color", &self.workspace_focused_label_color),
+            ("workspace_focused_icon_color", &self.workspace_focused_icon_color),
+            ("workspace_unfocused_label_color", &self.workspace_unfocused_label_color),
+            ("workspace_unfocused_icon_color", &self.workspace_unfocused_icon_color),
+            ("workspace_gradient_start", &self.workspace_gradient_start),
+            ("workspace_gradient_end", &self.workspace_gradient_end),
+            ("battery_charging_color", &self.battery_charging_color),
+            ("battery_low_color", &self.battery_low_color),
+            ("battery_normal_color", &self.battery_normal_color),
+            ("battery.warning_color", &self.battery.warning_color),
+        ];
+        for (key, color) in colors {
+            if !is_hex_color(color) {
+                issues.push(Issue::new(key, format!("invalid color \"{}\", expected 0xAARRGGBB", color)));
+            }
+        }
+        if !is_border_color(&self.border_active_color) {
+            issues.push(Issue::new(
+                "border_active_color",
+                format!(
+                    "invalid color \"{}\", expected 0xAARRGGBB or gradient(top_left=0xAARRGGBB,bottom_right=0xAARRGGBB)",
+                    self.border_active_color
+                ),
+            ));
+        }
+
+        for key in self.workspace_style.keys() {
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                issues.push(Issue::new(
+                    &format!("workspace_style.{}", key),
+                    "property names can't be empty or contain spaces",
+                ));
+            }
+        }
+        for (key, format) in [("clock.format", &self.clock.format), ("clock.zone_format", &self.clock.zone_format)] {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                issues.push(Issue::new(key, format!("invalid strftime format \"{}\"", format)));
+            }
+        }
+        if Locale::try_from(self.clock.locale.as_str()).is_err() {
+            issues.push(Issue::new(
+                "clock.locale",
+                format!("unknown locale \"{}\", expected e.g. en_US or de_DE", self.clock.locale),
+            ));
+        }
+        for (i, zone) in self.clock.zones.iter().enumerate() {
+            if zone.tz.parse::<chrono_tz::Tz>().is_err() {
+                issues.push(Issue::new(
+                    &format!("clock.zones[{}].tz", i),
+                    format!("unknown time zone \"{}\", expected e.g. America/New_York", zone.tz),
+                ));
+            }
+        }
+
+        for (i, ws) in self.workspace_order.iter().enumerate() {
+            if ws.is_empty() {
+                issues.push(Issue::new(&format!("workspace_order[{}]", i), "workspace name can't be empty"));
+            }
+        }
+
+        issues
     }
 
     /// Save configuration to a file
@@ -123,8 +720,9 @@ impl Config {
                 .map_err(|e| format!("Failed to create config directory: {}", e))?;
         }
 
-        let contents = serde_json::to_string_pretty(self)
+        let value = serde_json::to_value(self)
             .map_err(|e| format!("Failed to serialize config: {}", e))?;
+        let contents = config_format::serialize(&value, Format::of(path)?)?;
 
         fs::write(path, contents)
             .map_err(|e| format!("Failed to write config file: {}", e))?;
@@ -133,6 +731,139 @@ impl Config {
     }
 }
 
+/// Reset the innermost key of `path` that has a default of its own (the
+/// whole config for an empty path); false if `value` didn't change
+fn reset_to_default(value: &mut Value, defaults: &Value, path: &str) -> bool {
+    let keys: Vec<String> = path
+        .split('[')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .filter(|k| !k.is_empty())
+        .map(|k| k.replace('~', "~0").replace('/', "~1"))
+        .collect();
+    for n in (0..=keys.len()).rev() {
+        let pointer: String = keys[..n].iter().map(|k| format!("/{}", k)).collect();
+        if let (Some(default), Some(slot)) = (defaults.pointer(&pointer), value.pointer_mut(&pointer)) {
+            if slot == default {
+                return false;
+            }
+            *slot = default.clone();
+            return true;
+        }
+    }
+    false
+}
+
+/// A problem found in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Key path, e.g. "display_map.DELL U2720Q" (empty for the whole file)
+    pub path: String,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// Note which layer the problem is in
+    fn from(mut self, source: &Source) -> Self {
+        self.message = format!("{} (from {})", self.message, source);
+        self
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Outcome of checking a config file
+#[derive(Debug, Default)]
+pub struct ConfigCheck {
+    /// The parsed config, with values of the wrong type reset to their
+    /// defaults; none if the file could not be parsed at all
+    pub config: Option<Config>,
+    pub errors: Vec<Issue>,
+    pub warnings: Vec<Issue>,
+    /// Schema version the file was written for
+    pub from_version: u32,
+    /// What migrating the file to the current version changed
+    pub migration_notes: Vec<String>,
+}
+
+/// Sections whose keys are fields rather than free-form map entries
+const SECTIONS: &[&str] = &["intervals", "intervals.on_ac", "intervals.on_battery", "clock", "battery"];
+
+/// Warn about keys in `object` that `known` (the serialized defaults) lacks
+fn unknown_keys(object: &serde_json::Map<String, Value>, known: &serde_json::Map<String, Value>, prefix: &str, warnings: &mut Vec<Issue>) {
+    for (key, value) in object {
+        let path = format!("{}{}", prefix, key);
+        match known.get(key) {
+            Some(Value::Object(known)) if SECTIONS.contains(&path.as_str()) => {
+                if let Some(object) = value.as_object() {
+                    unknown_keys(object, known, &format!("{}.", path), warnings);
+                }
+            }
+            Some(_) => {}
+            None => {
+                let message = match known.keys().find(|k| edit_distance(k, key) <= 2) {
+                    Some(similar) => format!("unknown key, ignored (did you mean \"{}\"?)", similar),
+                    None => "unknown key, ignored".to_string(),
+                };
+                warnings.push(Issue::new(&path, message));
+            }
+        }
+    }
+}
+
+/// `0xAARRGGBB`
+fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 8 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// A hex color, or a JankyBorders `gradient(...)`/`glow(...)` of hex colors
+fn is_border_color(color: &str) -> bool {
+    let inner = ["gradient(", "glow("]
+        .iter()
+        .find_map(|prefix| color.strip_prefix(prefix)?.strip_suffix(')'));
+    match inner {
+        Some(inner) => inner.split(',').all(|part| match part.split_once('=') {
+            Some((_, value)) => is_hex_color(value.trim()),
+            None => is_hex_color(part.trim()),
+        }),
+        None => is_hex_color(color),
+    }
+}
+
+/// Levenshtein distance, used to suggest the key a typo was meant to be
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,10 +871,308 @@ mod tests {
     #[test]
     fn test_default_config() {
         let config = Config::default();
-        assert_eq!(config.clock_interval, 15);
-        assert_eq!(config.battery_interval, 120);
-        assert_eq!(config.brew_interval, 3600);
-        assert_eq!(config.teams_interval, 30);
-        assert_eq!(config.system_interval, 5);
+        assert_eq!(config.intervals.clock, 15);
+        assert_eq!(config.intervals.battery, 120);
+        assert_eq!(config.intervals.brew, 3600);
+        assert_eq!(config.intervals.teams, 30);
+        assert_eq!(config.intervals.system, 5);
+        assert_eq!(config.version, migration::CURRENT_VERSION);
+        assert!(config.display_map.is_empty());
+    }
+
+    #[test]
+    fn test_partial_config_keeps_other_defaults() {
+        let check = Config::check_str(r#"{"version": 2, "intervals": {"battery": 60}}"#, Format::Json);
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        let config = check.config.unwrap();
+        assert_eq!(config.intervals.battery, 60);
+        assert_eq!(config.intervals.clock, 15);
+        assert_eq!(config.workspace_anchor, "front_app");
+
+        // Pre-versioning files are migrated first
+        let check = Config::check_str(r#"{"battery_interval": 60}"#, Format::Json);
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        assert_eq!(check.from_version, 1);
+        assert_eq!(check.config.unwrap().intervals.battery, 60);
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        let json = serde_json::to_string(&Config::default()).unwrap();
+        let check = Config::check_str(&json, Format::Json);
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        assert!(check.warnings.is_empty(), "{:?}", check.warnings);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_warnings() {
+        let check = Config::check_str(
+            r#"{"version": 2, "colour": "red", "intervals": {"batery": 60}, "sinc": "cli"}"#, Format::Json,
+        );
+        assert!(check.errors.is_empty());
+        assert_eq!(check.warnings, vec![
+            Issue::new("colour", "unknown key, ignored"),
+            Issue::new("intervals.batery", "unknown key, ignored (did you mean \"battery\"?)"),
+            Issue::new("sinc", "unknown key, ignored (did you mean \"sink\"?)"),
+        ]);
+        assert_eq!(check.config.unwrap().intervals.battery, 120);
+    }
+
+    #[test]
+    fn test_type_errors_have_key_paths() {
+        let check = Config::check_str(r#"{"display_map": {"DELL U2720Q": "three"}}"#, Format::Json);
+        assert_eq!(check.errors.len(), 1);
+        assert_eq!(check.errors[0].path, "display_map.DELL U2720Q");
+        assert!(check.errors[0].message.contains("expected u32"), "{}", check.errors[0]);
+        assert!(check.config.unwrap().display_map.is_empty());
+
+        let check = Config::check_str(r#"{"sink": "pigeon"}"#, Format::Json);
+        assert_eq!(check.errors[0].path, "sink");
+    }
+
+    #[test]
+    fn test_type_errors_keep_the_other_keys() {
+        let check = Config::check_str(
+            r#"{
+                "version": 2,
+                "sink": "pigeon",
+                "intervals": {"clock": "soon", "battery": 60},
+                "workspace_order": ["m", 1],
+                "display_map": {"DELL": 2},
+                "battery_low_color": "0xffff0000"
+            }"#, Format::Json,
+        );
+        let paths: Vec<&str> = check.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["intervals.clock", "sink", "workspace_order[1]"]);
+
+        let config = check.config.unwrap().with_defaults_for(&check.errors);
+        assert_eq!(config.sink, SinkKind::default());
+        assert_eq!(config.intervals.clock, 15);
+        assert_eq!(config.intervals.battery, 60);
+        assert!(config.workspace_order.is_empty());
+        assert_eq!(config.display_map.get("DELL"), Some(&2));
+        assert_eq!(config.battery_low_color, "0xffff0000");
+    }
+
+    #[test]
+    fn test_toml_config() {
+        let check = Config::check_str(
+            "version = 2\nworkspace_order = [\"m\"]\n\n[intervals]\nbattery = 60\nbatery = 1\n",
+            Format::Toml,
+        );
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        assert_eq!(check.warnings[0].path, "intervals.batery");
+        let config = check.config.unwrap();
+        assert_eq!(config.intervals.battery, 60);
+        assert_eq!(config.workspace_order, vec!["m"]);
+
+        let check = Config::check_str("[intervals]\nclock = \"soon\"\n", Format::Toml);
+        assert_eq!(check.errors[0].path, "intervals.clock");
+
+        let check = Config::check_str("version = 2\nsink = \n", Format::Toml);
+        assert!(check.errors[0].message.contains("line 2, column 8"), "{}", check.errors[0]);
+    }
+
+    #[test]
+    fn test_invalid_json_reports_position() {
+        let check = Config::check_str("{\n  \"version\": 2,\n}", Format::Json);
+        assert_eq!(check.errors.len(), 1);
+        assert!(check.errors[0].message.contains("line 3, column 1"), "{}", check.errors[0]);
+    }
+
+    #[test]
+    fn test_validation() {
+        let check = Config::check_str(
+            r#"{
+                "version": 2,
+                "intervals": {"clock": 0},
+                "battery_low_color": "red",
+                "workspace_gradient_end": "0xffd8a6",
+                "border_active_color": "gradient(top_left=0xffbb60cd,bottom_right=oops)",
+                "workspace_order": ["1", ""]
+            }"#, Format::Json,
+        );
+        let paths: Vec<&str> = check.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec![
+            "intervals.clock",
+            "workspace_gradient_end",
+            "battery_low_color",
+            "border_active_color",
+            "workspace_order[1]",
+        ]);
+    }
+
+    #[test]
+    fn test_power_profiles() {
+        let check = Config::check_str(
+            r#"{"version": 2, "intervals": {"system": 2, "on_ac": {"brew": 1800}, "on_battery": {"clock": 60, "batery": 1}}}"#,
+            Format::Json,
+        );
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        assert_eq!(check.warnings[0].path, "intervals.on_battery.batery");
+        let config = check.config.unwrap();
+
+        let ac = config.for_power(PowerSource::Ac).intervals;
+        assert_eq!((ac.brew, ac.system, ac.clock), (1800, 2, 15));
+        let battery = config.for_power(PowerSource::Battery).intervals;
+        assert_eq!((battery.brew, battery.system, battery.clock), (14400, 30, 60));
+
+        let defaults = Config::default().intervals.on(PowerSource::Battery);
+        assert_eq!((defaults.brew, defaults.system), (14400, 30));
+
+        let check = Config::check_str(r#"{"intervals": {"on_battery": {"system": 0}}}"#, Format::Json);
+        assert_eq!(check.errors[0].path, "intervals.on_battery.system");
+        let config = check.config.unwrap().with_defaults_for(&check.errors);
+        assert_eq!(config.intervals.on_battery.system, Some(30));
+    }
+
+    #[test]
+    fn test_clock_validation() {
+        let check = Config::check_str(
+            r#"{
+                "version": 2,
+                "clock": {
+                    "format": "%H:%Q",
+                    "locale": "xx_XX",
+                    "zones": [
+                        {"label": "NYC", "tz": "America/New_York"},
+                        {"label": "MARS", "tz": "Mars/Olympus_Mons"}
+                    ],
+                    "zones_in": "popup"
+                }
+            }"#, Format::Json,
+        );
+        let paths: Vec<&str> = check.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["clock.format", "clock.locale", "clock.zones[1].tz"]);
+
+        let config = check.config.unwrap().with_defaults_for(&check.errors);
+        assert_eq!(config.clock.format, ClockConfig::default().format);
+        assert!(config.clock.zones.is_empty());
+        assert_eq!(config.clock.zones_in, Placement::Popup);
+    }
+
+    #[test]
+    fn test_icon_settings() {
+        let check = Config::check_str(
+            r#"{"version": 2, "icons": "sf-symbols", "battery": {"steps": 1, "time_in": "popup"}}"#, Format::Json,
+        );
+        let paths: Vec<&str> = check.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["battery.steps"]);
+
+        let config = check.config.unwrap().with_defaults_for(&check.errors);
+        assert_eq!(config.icons, IconSet::SfSymbols);
+        assert_eq!(config.battery.steps, None);
+        assert_eq!(config.battery.time_in, Placement::Popup);
+
+        let check = Config::check_str(r#"{"version": 2, "icons": "emoji"}"#, Format::Json);
+        assert_eq!(check.errors[0].path, "icons");
+    }
+
+    #[test]
+    fn test_battery_alert_validation() {
+        let check = Config::check_str(
+            r#"{"version": 2, "battery": {"warning": 15, "critical": 30, "warning_color": "red"}}"#, Format::Json,
+        );
+        let paths: Vec<&str> = check.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["battery.critical", "battery.warning_color"]);
+
+        // With warnings off the critical threshold can be anything
+        let check = Config::check_str(r#"{"version": 2, "battery": {"warning": 0, "critical": 30}}"#, Format::Json);
+        assert!(check.errors.is_empty());
+        let check = Config::check_str(r#"{"version": 2, "battery": {"warning": 120}}"#, Format::Json);
+        assert_eq!(check.errors[0].path, "battery.warning");
+    }
+
+    #[test]
+    fn test_invalid_values_fall_back_individually() {
+        let check = Config::check_str(
+            r#"{"version": 2, "intervals": {"clock": 0, "battery": 60}, "workspace_order": [""]}"#, Format::Json,
+        );
+        let config = check.config.unwrap().with_defaults_for(&check.errors);
+        assert_eq!(config.intervals.clock, 15);
+        assert_eq!(config.intervals.battery, 60);
+        assert!(config.workspace_order.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_file_keeps_a_backup() {
+        let dir = env::temp_dir().join(format!("sketchybartender-migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sketchybartender.json");
+        let v1 = r#"{"clock_interval": 30, "battery_low_color": "0xfffb4934"}"#;
+        fs::write(&path, v1).unwrap();
+
+        let (from, backup, notes) = Config::migrate_file(&path).unwrap().unwrap();
+        assert_eq!(from, 1);
+        assert_eq!(backup, dir.join("sketchybartender.json.v1.bak"));
+        assert_eq!(notes, vec!["moved `clock_interval` to `intervals.clock`"]);
+        assert_eq!(fs::read_to_string(&backup).unwrap(), v1);
+
+        let migrated: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(migrated, serde_json::json!({
+            "version": 2,
+            "intervals": {"clock": 30},
+            "battery_low_color": "0xfffb4934",
+        }));
+
+        // Already current: nothing to do
+        assert_eq!(Config::migrate_file(&path).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_layers_merge_in_order() {
+        let dir = env::temp_dir().join(format!("sketchybartender-layers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("sketchybartender.json");
+        let host = dir.join("sketchybartender.laptop.toml");
+        fs::write(&user, r#"{"intervals": {"clock": 30, "battery": 60}, "workspace_order": ["m"]}"#).unwrap();
+        fs::write(&host, "version = 2\n[intervals]\nbattery = 0\nsystem = 2\n").unwrap();
+        let vars = [
+            ("SKETCHYBARTENDER_INTERVALS__SYSTEM", "10"),
+            ("SKETCHYBARTENDER_SINC", "cli"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        let paths = [user.clone(), host.clone(), dir.join("sketchybartender.desktop.json")];
+        let (check, merged) = Config::check_layers_from(&paths, vars);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let config = check.config.unwrap();
+        assert_eq!(config.intervals.clock, 30);
+        assert_eq!(config.intervals.system, 10);
+        assert_eq!(config.workspace_order, vec!["m"]);
+        assert_eq!(check.from_version, 1);
+        assert_eq!(check.errors, vec![Issue::new(
+            "intervals.battery",
+            format!("must be at least 1 second (from {})", host.display()),
+        )]);
+        assert_eq!(check.warnings[0].path, "sinc");
+        assert!(check.warnings[0].message.ends_with("(from $SKETCHYBARTENDER_SINC)"));
+
+        assert_eq!(merged.source_of("intervals.clock"), &Source::File(user));
+        assert_eq!(merged.source_of("intervals.battery"), &Source::File(host));
+        assert_eq!(merged.source_of("sink"), &Source::Default);
+    }
+
+    #[test]
+    fn test_border_colors() {
+        assert!(is_border_color("0xffbb60cd"));
+        assert!(is_border_color("gradient(top_left=0xffbb60cd,bottom_right=0xffffad00)"));
+        assert!(is_border_color("glow(0xffbb60cd)"));
+        assert!(!is_border_color("gradient(top_left=0xffbb60cd"));
+    }
+
+    #[test]
+    fn test_display_map_is_optional() {
+        let mut value = serde_json::to_value(Config::default()).unwrap();
+        value.as_object_mut().unwrap().remove("display_map");
+        let config: Config = serde_json::from_value(value.clone()).unwrap();
+        assert!(config.display_map.is_empty());
+
+        value["display_map"] = serde_json::json!({"2": 3, "3": 2, "DELL U2720Q": 1});
+        let config: Config = serde_json::from_value(value).unwrap();
+        assert_eq!(config.display_map["DELL U2720Q"], 1);
+        assert_eq!(config.display_map["2"], 3);
     }
 }
@@ -0,0 +1,180 @@
+//! Config file formats
+//!
+//! Config files may be written in TOML or JSON, picked by extension. When
+//! both `<name>.toml` and `<name>.json` exist, the TOML file wins. Both are
+//! parsed into a `serde_json::Value` first so migration and checking work the
+//! same for either, and parse errors carry a line and column.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+/// Supported formats, in order of precedence
+pub const PRECEDENCE: &[Format] = &[Format::Toml, Format::Json];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+        }
+    }
+
+    /// Format of `path`, from its extension
+    pub fn of(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("json") => Ok(Format::Json),
+            _ => Err(format!("{:?} is neither a .toml nor a .json file", path)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Toml => f.write_str("TOML"),
+            Format::Json => f.write_str("JSON"),
+        }
+    }
+}
+
+/// The config file called `name` in `dir`: the first format in `PRECEDENCE`
+/// that exists, or the JSON path if none does (that's where a default config
+/// is created). Also returns the files of other formats it takes precedence
+/// over, so callers can warn about them.
+pub fn resolve(dir: &Path, name: &str) -> (PathBuf, Vec<PathBuf>) {
+    let mut existing = PRECEDENCE
+        .iter()
+        .map(|format| dir.join(format!("{}.{}", name, format.extension())))
+        .filter(|p| p.exists());
+    match existing.next() {
+        Some(path) => (path, existing.collect()),
+        None => (dir.join(format!("{}.json", name)), Vec::new()),
+    }
+}
+
+/// 1-based line and column of byte `offset` in `contents`
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    (line, column)
+}
+
+/// Parse `contents` into a JSON value
+pub fn parse(contents: &str, format: Format) -> Result<Value, String> {
+    match format {
+        Format::Json => serde_json::from_str(contents).map_err(|e| {
+            // serde_json appends " at line L column C" itself; put it up front
+            let message = e.to_string();
+            let message = message.split(" at line ").next().unwrap_or_default();
+            format!("invalid JSON at line {}, column {}: {}", e.line(), e.column(), message)
+        }),
+        Format::Toml => toml::from_str(contents).map_err(|e| {
+            let message = e.message().trim();
+            match e.span() {
+                Some(span) => {
+                    let (line, column) = line_column(contents, span.start);
+                    format!("invalid TOML at line {}, column {}: {}", line, column, message)
+                }
+                None => format!("invalid TOML: {}", message),
+            }
+        }),
+    }
+}
+
+/// Serialize `value` for writing back to a file
+pub fn serialize(value: &Value, format: Format) -> Result<String, String> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(value)
+            .map(|s| s + "\n")
+            .map_err(|e| format!("Failed to serialize config: {}", e)),
+        Format::Toml => {
+            toml::to_string_pretty(value).map_err(|e| format!("Failed to serialize config: {}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_format_of() {
+        assert_eq!(Format::of(Path::new("a/b.toml")), Ok(Format::Toml));
+        assert_eq!(Format::of(Path::new("b.json")), Ok(Format::Json));
+        assert!(Format::of(Path::new("b.yaml")).is_err());
+    }
+
+    #[test]
+    fn test_same_value_from_both_formats() {
+        let toml = r#"
+version = 2
+sink = "cli"
+
+[intervals]
+clock = 30
+
+[display_map]
+"DELL U2720Q" = 3
+
+[workspace_style]
+"icon.font" = "sketchybar-app-font:Regular:13.0"
+"#;
+        let json = r#"{
+            "version": 2,
+            "sink": "cli",
+            "intervals": {"clock": 30},
+            "display_map": {"DELL U2720Q": 3},
+            "workspace_style": {"icon.font": "sketchybar-app-font:Regular:13.0"}
+        }"#;
+        assert_eq!(parse(toml, Format::Toml).unwrap(), parse(json, Format::Json).unwrap());
+    }
+
+    #[test]
+    fn test_errors_have_line_and_column() {
+        let err = parse("version = 2\nsink = cli\n", Format::Toml).unwrap_err();
+        assert!(err.starts_with("invalid TOML at line 2, column 8:"), "{}", err);
+
+        let err = parse("{\n  \"version\": 2,\n}", Format::Json).unwrap_err();
+        assert!(err.starts_with("invalid JSON at line 3, column 1:"), "{}", err);
+    }
+
+    #[test]
+    fn test_serialize_round_trips() {
+        let value = json!({
+            "version": 2,
+            "intervals": {"clock": 30},
+            "workspace_order": ["m", "1"],
+            "workspace_style": {"icon.font": "Mono:Bold:13.0"},
+        });
+        for format in PRECEDENCE {
+            let text = serialize(&value, *format).unwrap();
+            assert_eq!(parse(&text, *format).unwrap(), value, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_resolve_prefers_toml() {
+        let dir = std::env::temp_dir().join(format!("sketchybartender-format-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(resolve(&dir, "cfg"), (dir.join("cfg.json"), vec![]));
+        fs::write(dir.join("cfg.json"), "{}").unwrap();
+        assert_eq!(resolve(&dir, "cfg"), (dir.join("cfg.json"), vec![]));
+        fs::write(dir.join("cfg.toml"), "").unwrap();
+        assert_eq!(resolve(&dir, "cfg"), (dir.join("cfg.toml"), vec![dir.join("cfg.json")]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,27 +1,117 @@
 use std::env;
 use std::fs;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use serde_json::Value;
+
 use crate::handlers::{
     DaemonState,
+    HandlerResult,
     handle_aerospace_focus,
     handle_battery_refresh,
-    handle_brew_refresh,
     handle_brew_upgrade,
-    handle_clock_refresh,
     handle_focus_refresh,
-    handle_system_refresh,
+    handle_sleep,
+    handle_system_wake,
     handle_teams_clicked,
     handle_teams_refresh,
     handle_volume_refresh,
     handle_workspace_refresh,
 };
+use crate::protocol::Response;
+use crate::reload;
+use crate::render;
+
+/// Daemon state plus the last value pushed to every item
+fn dump_state(state: &Arc<Mutex<DaemonState>>) -> Result<Value, String> {
+    let mut snapshot = state
+        .lock()
+        .map(|s| s.snapshot())
+        .map_err(|_| "Daemon state lock poisoned".to_string())?;
+    snapshot["items"] = render::snapshot();
+    Ok(snapshot)
+}
+
+/// Pause, resume or trigger a scheduled job
+fn control_job(command: &str, job: &str, state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    let scheduler = state
+        .lock()
+        .map(|s| Arc::clone(&s.scheduler))
+        .map_err(|_| "Daemon state lock poisoned".to_string())?;
+    match command {
+        "pause-job" => scheduler.pause(job),
+        "resume-job" => scheduler.resume(job),
+        _ => scheduler.trigger(job),
+    }
+}
+
+/// Run a single request line and produce the reply sent back to the client
+pub fn dispatch(line: &str, state: &Arc<Mutex<DaemonState>>) -> Response {
+    let parts: Vec<&str> = line.trim().splitn(2, ' ').collect();
+    let result: Result<Option<Value>, String> = match parts.first().copied() {
+        Some("on-volume-changed") => {
+            let vol = parts.get(1).and_then(|s| s.parse().ok());
+            handle_volume_refresh(vol, state).map(|_| None)
+        }
+        Some("on-focus-changed") => {
+            let app_name = parts.get(1).map(|s| s.to_string());
+            handle_focus_refresh(app_name, state).map(|_| None)
+        }
+        Some("on-workspace-changed") => handle_workspace_refresh(state).map(|_| None),
+        Some("on-aerospace-focus") => match parts.get(1).map(|s| s.trim().to_string()) {
+            Some(workspace) if !workspace.is_empty() => {
+                handle_aerospace_focus(workspace, state).map(|_| None)
+            }
+            _ => Err("Usage: on-aerospace-focus <workspace>".to_string()),
+        },
+        Some("on-brew-clicked") => handle_brew_upgrade().map(|_| None),
+        Some("on-teams-clicked") => handle_teams_clicked().map(|_| None),
+        Some("trigger-teams-refresh") => handle_teams_refresh().map(|_| None),
+        Some("on-display-configuration-changed") => handle_workspace_refresh(state).map(|_| None),
+        Some("on-power-source-changed") => {
+            let power_source = parts.get(1).map(|s| s.to_string());
+            handle_battery_refresh(power_source, state).map(|_| None)
+        }
+        Some("status") | Some("dump-state") => dump_state(state).map(Some),
+        Some("repush") => render::repush_all()
+            .map(|_| None)
+            .map_err(|e| format!("Failed to re-push bar state: {}", e)),
+        Some("on-system-sleep") | Some("on-display-sleep") => handle_sleep(state).map(|_| None),
+        Some("on-system-wake") | Some("on-display-wake") => handle_system_wake(state).map(|_| None),
+        Some("reload-config") => reload::reload_config(state).map(|_| None),
+        Some(command @ ("pause-job" | "resume-job" | "trigger-job")) => {
+            match parts.get(1).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                Some(job) => control_job(command, job, state).map(|_| None),
+                None => Err(format!("Usage: {} <job>", command)),
+            }
+        }
+
+        _ => {
+            eprintln!("Unknown message: {}", line);
+            return Response::UnknownCommand {
+                command: parts.first().copied().unwrap_or_default().to_string(),
+            };
+        }
+    };
+
+    if let Err(e) = &result {
+        eprintln!("{}", e);
+    }
+    Response::from(result)
+}
 
 pub fn handle_client(stream: UnixStream, state: Arc<Mutex<DaemonState>>) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("Failed to clone client stream: {}", e);
+            return;
+        }
+    };
     let reader = BufReader::new(stream);
 
     for line in reader.lines() {
@@ -29,43 +119,15 @@ pub fn handle_client(stream: UnixStream, state: Arc<Mutex<DaemonState>>) {
             Ok(l) => l,
             Err(_) => break,
         };
+        if line.trim().is_empty() {
+            continue;
+        }
 
-        let parts: Vec<&str> = line.trim().splitn(2, ' ').collect();
-        match parts.get(0).map(|s| *s) {
-            Some("on-volume-changed") => {
-                let vol = parts.get(1).and_then(|s| s.parse().ok());
-                handle_volume_refresh(vol);
-            }
-            Some("on-focus-changed") => {
-                let app_name = parts.get(1).map(|s| s.to_string());
-                handle_focus_refresh(app_name, &state);
-            }
-            Some("on-workspace-changed") => handle_workspace_refresh(&state),
-            Some("on-aerospace-focus") => {
-                if let Some(workspace) = parts.get(1).map(|s| s.to_string()) {
-                    handle_aerospace_focus(workspace, &state);
-                }
-            }
-            Some("on-brew-clicked") => handle_brew_upgrade(),
-            Some("on-teams-clicked") => handle_teams_clicked(),
-            Some("trigger-teams-refresh") => handle_teams_refresh(),
-            Some("on-display-configuration-changed") => handle_workspace_refresh(&state),
-            Some("on-power-source-changed") => {
-                let power_source = parts.get(1).map(|s| s.to_string());
-                let config = state.lock().map(|s| s.config.clone()).unwrap_or_default();
-                handle_battery_refresh(power_source, &config);
-            }
-            Some("on-system-wake") => {
-                handle_workspace_refresh(&state);
-                let config = state.lock().map(|s| s.config.clone()).unwrap_or_default();
-                handle_battery_refresh(None, &config);
-                handle_clock_refresh();
-                handle_teams_refresh();
-            }
+        let response = dispatch(&line, &state);
 
-            _ => {
-                eprintln!("Unknown message: {}", line);
-            }
+        // The client may have gone away without waiting for the reply
+        if writeln!(writer, "{}", response.to_line()).is_err() {
+            break;
         }
     }
 }
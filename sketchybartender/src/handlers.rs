@@ -2,13 +2,21 @@ use std::collections::{HashMap, HashSet};
 use std::process::Command;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use crate::aerospace;
 use crate::aerospace_focus;
+use crate::alerts::{Alert, BatteryAlerts};
+use crate::batch::SketchybarBatch;
+use crate::clock::SystemClock;
+use crate::coalesce::CoalescingRunner;
 use crate::icon_map;
-use crate::mach_client;
-use crate::providers;
+use crate::ordering;
+use crate::config::{PowerSource, Placement};
+use crate::providers::{self, Supply};
+use crate::registry::Registry;
+use crate::render;
+use crate::scheduler::Scheduler;
 
 /// Parse a color like "0xffbb60cd" to (r, g, b) tuple
 fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
@@ -53,67 +61,6 @@ fn get_workspace_gradient_colors(config: &crate::config::Config) -> Vec<String>
     }
 }
 
-/// A builder for batching sketchybar commands
-#[derive(Debug, Default)]
-pub struct SketchybarBatch {
-    args: Vec<String>,
-}
-
-impl SketchybarBatch {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    /// Set properties on an item
-    pub fn set(&mut self, item: &str, props: &[(&str, &str)]) -> &mut Self {
-        self.args.push("--set".to_string());
-        self.args.push(item.to_string());
-        for (key, value) in props {
-            // Quote the value if it contains spaces or special characters
-            let formatted_value = if value.contains(' ') || value.is_empty() {
-                format!("{}=\"{}\"", key, value)
-            } else {
-                format!("{}={}", key, value)
-            };
-            self.args.push(formatted_value);
-        }
-        self
-    }
-
-    /// Add animation with curve and duration
-    pub fn animate(&mut self, curve: &str, duration: u32) -> &mut Self {
-        self.args.push("--animate".to_string());
-        self.args.push(curve.to_string());
-        self.args.push(duration.to_string());
-        self
-    }
-
-    /// Execute the batched commands
-    pub fn execute(&self) -> Result<(), std::io::Error> {
-        if self.args.is_empty() {
-            return Ok(());
-        }
-
-        // Convert args to a single command string for mach port
-        let command = self.args.join(" ");
-
-        // Debug: print the command being sent
-        eprintln!("[DEBUG] Sending to sketchybar: {}", command);
-
-        // Send via mach port
-        match mach_client::sketchybar(&command) {
-            Ok(_) => {
-                eprintln!("[DEBUG] Command sent successfully");
-                Ok(())
-            }
-            Err(e) => {
-                eprintln!("[DEBUG] Command failed: {}", e);
-                Err(std::io::Error::new(std::io::ErrorKind::Other, format!("sketchybar mach command failed: {}", e)))
-            }
-        }
-    }
-}
-
 /// Convenience function to set properties on a single item
 fn set_item(item: &str, props: &[(&str, &str)]) -> Result<(), std::io::Error> {
     let mut batch = SketchybarBatch::new();
@@ -121,19 +68,80 @@ fn set_item(item: &str, props: &[(&str, &str)]) -> Result<(), std::io::Error> {
     batch.execute()
 }
 
-/// Update the clock item
-fn update_clock(time: &str) -> Result<(), std::io::Error> {
-    set_item("clock", &[("label", time)])
+/// Popup item for the `i`th extra clock zone
+fn clock_zone_item(i: usize) -> String {
+    format!("clock.zone.{}", i)
 }
 
-/// Update the battery item
-fn update_battery(icon: &str, icon_color: &str, label_color: &str, percentage: u8) -> Result<(), std::io::Error> {
-    set_item("battery", &[
-        ("icon", icon),
-        ("icon.color", icon_color),
-        ("label.color", label_color),
-        ("label", &format!("{}%", percentage)),
-    ])
+/// Build the clock item update. Extra zones go in the label or in popup
+/// items; `added` tells which popup items are on the bar, so leftovers from
+/// a longer zone list (or from popup mode) are removed.
+fn clock_batch(
+    clock: &providers::ClockText,
+    config: &crate::config::ClockConfig,
+    added: impl Fn(&str) -> bool,
+) -> SketchybarBatch {
+    let mut batch = SketchybarBatch::new();
+    let popup = config.zones_in == Placement::Popup;
+    let label = if popup || clock.zones.is_empty() {
+        clock.time.clone()
+    } else {
+        format!("{} · {}", clock.time, clock.zones.join(" · "))
+    };
+    batch.set("clock", &[("label", &label)]);
+
+    let shown = if popup { clock.zones.len() } else { 0 };
+    for (i, zone) in clock.zones.iter().take(shown).enumerate() {
+        let item = clock_zone_item(i);
+        batch
+            .add(&item, "popup.clock")
+            .set(&item, &[("icon.drawing", "off"), ("label", zone)]);
+    }
+    let mut i = shown;
+    while added(&clock_zone_item(i)) {
+        batch.remove(&clock_zone_item(i));
+        i += 1;
+    }
+    batch
+}
+
+/// Popup item with the battery's charge state and time remaining
+const BATTERY_TIME_ITEM: &str = "battery.time";
+
+/// Build the battery item update. The time until empty or full goes in the
+/// label or in a popup item; `added` tells whether the popup item is on the
+/// bar, so it is removed when there's nothing to put in it.
+fn battery_batch(
+    info: &providers::BatteryInfo,
+    config: &crate::config::Config,
+    added: impl Fn(&str) -> bool,
+) -> SketchybarBatch {
+    let mut batch = SketchybarBatch::new();
+    let popup = config.battery.time_in == Placement::Popup;
+    let remaining = info.remaining();
+    let label = match (&remaining, popup) {
+        (Some(time), false) => format!("{}% · {}", info.percentage, time),
+        _ => format!("{}%", info.percentage),
+    };
+    batch.set("battery", &[
+        ("icon", info.icon(config)),
+        ("icon.color", info.icon_color(config)),
+        ("label.color", info.label_color(config)),
+        ("label", &label),
+    ]);
+
+    if popup {
+        let text = match &remaining {
+            Some(time) => format!("{} · {} remaining", info.state, time),
+            None => info.state.to_string(),
+        };
+        batch
+            .add(BATTERY_TIME_ITEM, "popup.battery")
+            .set(BATTERY_TIME_ITEM, &[("icon.drawing", "off"), ("label", &text)]);
+    } else if added(BATTERY_TIME_ITEM) {
+        batch.remove(BATTERY_TIME_ITEM);
+    }
+    batch
 }
 
 /// Update the volume item
@@ -187,60 +195,206 @@ fn update_teams(icon: &str, icon_color: &str, border_color: &str, notification_c
 pub struct DaemonState {
     /// Current front app (for deduplication)
     pub front_app: String,
-    /// Last workspace change timestamp for debouncing
-    pub last_workspace_change: Option<Instant>,
+    /// Coalesces bursts of workspace events into one trailing refresh
+    pub workspace_refresh: Arc<CoalescingRunner>,
     /// Previously rendered workspaces (to detect which ones need clearing)
     pub previous_workspaces: HashSet<String>,
+    /// The workspace style changed; recreate every item on the next refresh
+    pub restyle_workspaces: bool,
     /// Configuration
     pub config: crate::config::Config,
+    /// Runs every timed refresh
+    pub scheduler: Arc<Scheduler>,
+    /// CPU, memory and battery backends for this machine
+    pub registry: Arc<Registry>,
+    /// Last known power source; picks the interval profile
+    pub power: PowerSource,
+    /// Low battery alerts that already fired
+    pub battery_alerts: BatteryAlerts,
 }
 
 impl DaemonState {
     pub fn new(config: crate::config::Config) -> Self {
         Self {
             front_app: String::new(),
-            last_workspace_change: None,
+            workspace_refresh: Arc::new(CoalescingRunner::new(
+                Duration::from_millis(config.workspace_coalesce_ms),
+                Arc::new(SystemClock),
+            )),
             previous_workspaces: HashSet::new(),
+            restyle_workspaces: false,
+            scheduler: Arc::new(Scheduler::new(
+                config.for_power(PowerSource::default()),
+                Arc::new(SystemClock),
+            )),
+            registry: Arc::new(Registry::native()),
             config,
+            power: PowerSource::default(),
+            battery_alerts: BatteryAlerts::default(),
         }
     }
-}
 
-pub fn handle_clock_refresh() {
-    let time = providers::get_clock();
-    if let Err(e) = update_clock(&time) {
-        eprintln!("Failed to update clock: {}", e);
+    /// Swap in a reloaded config
+    pub fn replace_config(&mut self, config: crate::config::Config) {
+        self.workspace_refresh
+            .set_window(Duration::from_millis(config.workspace_coalesce_ms));
+        if config.workspace_style != self.config.workspace_style {
+            self.restyle_workspaces = true;
+        }
+        self.scheduler.set_config(config.for_power(self.power));
+        self.config = config;
+    }
+
+    /// Switch the job intervals to the profile for `power`; returns whether
+    /// it changed
+    pub fn set_power(&mut self, power: PowerSource) -> bool {
+        if power == self.power {
+            return false;
+        }
+        self.power = power;
+        self.scheduler.set_config(self.config.for_power(power));
+        true
+    }
+
+    /// JSON view of the daemon state, used by `dump-state`
+    pub fn snapshot(&self) -> serde_json::Value {
+        let workspaces = ordering::sort_workspaces(&self.previous_workspaces, &self.config.workspace_order);
+        serde_json::json!({
+            "front_app": self.front_app,
+            "previous_workspaces": workspaces,
+            "last_workspace_change_ms_ago": self
+                .workspace_refresh
+                .last_event()
+                .map(|t| t.elapsed().as_millis() as u64),
+            "config": self.config,
+            "power": self.power,
+            "suspended": self.scheduler.is_suspended(),
+            "jobs": self.scheduler.snapshot(),
+            "providers": self.registry.snapshot(),
+        })
     }
 }
 
-pub fn handle_battery_refresh(power_source: Option<String>, config: &crate::config::Config) {
-    if let Some(info) = providers::get_battery(power_source) {
-        if let Err(e) = update_battery(info.icon(), info.icon_color(config), info.label_color(config), info.percentage) {
-            eprintln!("Failed to update battery: {}", e);
+/// Result of a handler; the error is reported back to the socket client
+pub type HandlerResult = Result<(), String>;
+
+pub fn handle_clock_refresh(config: &crate::config::Config) -> HandlerResult {
+    let clock = providers::get_clock(&config.clock);
+    render::with_cache(|cache| clock_batch(&clock, &config.clock, |item| cache.is_added(item)))
+        .execute()
+        .map_err(|e| format!("Failed to update clock: {}", e))
+}
+
+/// Update the battery item, and the job intervals if the power source changed
+pub fn handle_battery_refresh(power_source: Option<String>, state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    let registry = state
+        .lock()
+        .map(|s| Arc::clone(&s.registry))
+        .map_err(|_| "Daemon state lock poisoned".to_string())?;
+    let Some(mut info) = registry.battery.read()? else {
+        // Nothing to show on a desktop; that's not a failure
+        return SketchybarBatch::new()
+            .set("battery", &[("drawing", "off")])
+            .execute()
+            .map_err(|e| format!("Failed to hide battery: {}", e));
+    };
+    // The event from sketchybar is newer than what the backend reports
+    if let Some(source) = power_source {
+        info.supply = if source == "AC" { Supply::Ac } else { Supply::Battery };
+    }
+    // Running off a UPS calls for saving power as much as a battery does
+    let power = if info.on_ac() {
+        PowerSource::Ac
+    } else {
+        PowerSource::Battery
+    };
+    let (config, alert) = {
+        let mut s = state
+            .lock()
+            .map_err(|_| "Daemon state lock poisoned".to_string())?;
+        if s.set_power(power) {
+            eprintln!("Power source changed, using {:?} intervals", power);
         }
+        let config = s.config.clone();
+        let alert = s.battery_alerts.update(info.percentage, info.on_ac(), &config.battery);
+        (config, alert)
+    };
+    let mut batch = render::with_cache(|cache| battery_batch(&info, &config, |item| cache.is_added(item)));
+    // In case it was hidden while no battery was found
+    batch.set("battery", &[("drawing", "on")]);
+    batch
+        .execute()
+        .map_err(|e| format!("Failed to update battery: {}", e))?;
+
+    let Some(alert) = alert else {
+        return Ok(());
+    };
+    run_alert_command(alert, info.percentage, &config.battery.alert_command);
+    battery_alert_batch(alert, &info, &config)
+        .execute()
+        .map_err(|e| format!("Failed to flash battery: {}", e))
+}
+
+/// Flash the battery item in the alert's color a few times, ending on its
+/// regular colors
+fn battery_alert_batch(alert: Alert, info: &providers::BatteryInfo, config: &crate::config::Config) -> SketchybarBatch {
+    let flash = match alert {
+        Alert::Warning => &config.battery.warning_color,
+        Alert::Critical => &config.battery_low_color,
+    };
+    let normal = info.icon_color(config);
+    let mut batch = SketchybarBatch::new();
+    for _ in 0..3 {
+        batch.animate("tanh", 10)
+             .set("battery", &[("icon.color", flash), ("label.color", flash)])
+             .animate("tanh", 10)
+             .set("battery", &[("icon.color", normal), ("label.color", info.label_color(config))]);
     }
+    batch
 }
 
-pub fn handle_brew_refresh() {
-    let info = providers::get_brew_outdated();
-    if let Err(e) = update_brew(info.icon(), info.formulae, info.casks) {
-        eprintln!("Failed to update brew: {}", e);
+/// Run the user's alert hook in the background
+fn run_alert_command(alert: Alert, percentage: u8, command: &str) {
+    if command.trim().is_empty() {
+        return;
     }
+    let command = command.to_string();
+    thread::spawn(move || {
+        let result = Command::new("sh")
+            .args(["-c", &command])
+            .env("SKETCHYBARTENDER_ALERT", alert.name())
+            .env("SKETCHYBARTENDER_BATTERY", percentage.to_string())
+            .output();
+
+        match result {
+            Ok(output) => {
+                if !output.status.success() {
+                    eprintln!("Battery alert command failed: {}", String::from_utf8_lossy(&output.stderr));
+                }
+            }
+            Err(e) => eprintln!("Failed to run battery alert command: {}", e),
+        }
+    });
+}
+
+pub fn handle_brew_refresh() -> HandlerResult {
+    let info = providers::get_brew_outdated();
+    update_brew(info.icon(), info.formulae, info.casks)
+        .map_err(|e| format!("Failed to update brew: {}", e))
 }
 
-pub fn handle_teams_refresh() {
+pub fn handle_teams_refresh() -> HandlerResult {
     let info = providers::get_teams_notifications();
-    if let Err(e) = update_teams(
+    update_teams(
         info.icon(),
         info.icon_color(),
         info.border_color(),
         info.notification_count,
-    ) {
-        eprintln!("Failed to update teams: {}", e);
-    }
+    )
+    .map_err(|e| format!("Failed to update teams: {}", e))
 }
 
-pub fn handle_teams_clicked() {
+pub fn handle_teams_clicked() -> HandlerResult {
     // Create continuous pulsing animation for the teams icon
     let mut batch = SketchybarBatch::new();
 
@@ -252,9 +406,9 @@ pub fn handle_teams_clicked() {
              .set("teams", &[("icon.y_offset", "0")]);
     }
 
-    if let Err(e) = batch.execute() {
-        eprintln!("Failed to start teams animation: {}", e);
-    }
+    let animation = batch
+        .execute()
+        .map_err(|e| format!("Failed to start teams animation: {}", e));
 
     thread::spawn(|| {
         // Open Microsoft Teams app
@@ -278,12 +432,18 @@ pub fn handle_teams_clicked() {
         if let Err(e) = set_item("teams", &[("icon.y_offset", "0")]) {
             eprintln!("Failed to reset teams icon offset: {}", e);
         }
-        handle_teams_refresh();
+        if let Err(e) = handle_teams_refresh() {
+            eprintln!("{}", e);
+        }
     });
+
+    animation
 }
 
-pub fn handle_system_refresh(prev_cpu: &mut Option<providers::CpuTicks>) {
-    let (info, cur_cpu) = providers::get_system_info(*prev_cpu);
+pub fn handle_system_refresh(registry: &Registry, prev_cpu: &mut Option<providers::CpuTicks>) -> HandlerResult {
+    let cur_cpu = registry.cpu.read().ok();
+    let memory = registry.memory.read().unwrap_or_default();
+    let info = providers::system_info(*prev_cpu, cur_cpu, memory);
     *prev_cpu = cur_cpu;
 
     let mut batch = SketchybarBatch::new();
@@ -293,19 +453,18 @@ pub fn handle_system_refresh(prev_cpu: &mut Option<providers::CpuTicks>) {
     batch.set("ram", &[
         ("label", &format!("{:.1}/{:.0}GB", info.ram_used_gb, info.ram_total_gb)),
     ]);
-    if let Err(e) = batch.execute() {
-        eprintln!("Failed to update cpu/ram: {}", e);
-    }
+    batch
+        .execute()
+        .map_err(|e| format!("Failed to update cpu/ram: {}", e))
 }
 
-pub fn handle_brew_upgrade() {
+pub fn handle_brew_upgrade() -> HandlerResult {
     // Set the refresh icon
-    if let Err(e) = set_item("brew", &[
+    set_item("brew", &[
         ("label", "\u{f409}"),
         ("label.y_offset", "0"),
-    ]) {
-        eprintln!("Failed to set brew refreshing label: {}", e);
-    }
+    ])
+    .map_err(|e| format!("Failed to set brew refreshing label: {}", e))?;
 
     // Create continuous pulsing animation for the label (refresh icon)
     // Since rotation is not supported, use a bouncing y_offset animation
@@ -319,9 +478,9 @@ pub fn handle_brew_upgrade() {
              .set("brew", &[("label.y_offset", "0")]);
     }
 
-    if let Err(e) = batch.execute() {
-        eprintln!("Failed to start brew animation: {}", e);
-    }
+    batch
+        .execute()
+        .map_err(|e| format!("Failed to start brew animation: {}", e))?;
 
     // Run brew upgrade in a separate thread so animation can continue
     thread::spawn(|| {
@@ -342,25 +501,32 @@ pub fn handle_brew_upgrade() {
         if let Err(e) = set_item("brew", &[("label.y_offset", "0")]) {
             eprintln!("Failed to reset brew offset: {}", e);
         }
-        handle_brew_refresh();
+        if let Err(e) = handle_brew_refresh() {
+            eprintln!("{}", e);
+        }
     });
+
+    Ok(())
 }
 
-pub fn handle_volume_refresh(vol: Option<u8>) {
+pub fn handle_volume_refresh(vol: Option<u8>, state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
     let info = if let Some(v) = vol {
         providers::VolumeInfo { percentage: v, muted: v == 0 }
     } else if let Some(v) = providers::get_volume() {
         v
     } else {
-        return;
+        return Err("Failed to read volume from osascript".to_string());
     };
 
-    if let Err(e) = update_volume(info.icon(), info.percentage) {
-        eprintln!("Failed to update volume: {}", e);
-    }
+    let config = state
+        .lock()
+        .map(|s| s.config.clone())
+        .map_err(|_| "Daemon state lock poisoned".to_string())?;
+    update_volume(info.icon(&config), info.percentage)
+        .map_err(|e| format!("Failed to update volume: {}", e))
 }
 
-pub fn handle_focus_refresh(app: Option<String>, state: &Arc<Mutex<DaemonState>>) {
+pub fn handle_focus_refresh(app: Option<String>, state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
     // Get app name from parameter or query aerospace
     let mut app_name = match app {
         Some(name) => name,
@@ -384,7 +550,7 @@ pub fn handle_focus_refresh(app: Option<String>, state: &Arc<Mutex<DaemonState>>
     
     // If app_name is empty, don't update (no focused window)
     if app_name.is_empty() {
-        return;
+        return Ok(());
     }
     
     // Remove "Microsoft " prefix from app names
@@ -397,14 +563,13 @@ pub fn handle_focus_refresh(app: Option<String>, state: &Arc<Mutex<DaemonState>>
     // Update state
     if let Ok(mut s) = state.lock() {
         if s.front_app == app_name {
-            return; // No change
+            return Ok(()); // No change
         }
         s.front_app = app_name.clone();
     }
 
-    if let Err(e) = update_front_app(icon, &app_name) {
-        eprintln!("Failed to update front_app: {}", e);
-    }
+    update_front_app(icon, &app_name)
+        .map_err(|e| format!("Failed to update front_app: {}", e))
 }
 
 /// Helper to build workspace label with bracket formatting
@@ -416,91 +581,20 @@ fn format_workspace_label(ws_id: &str, has_icon: bool) -> String {
     }
 }
 
-pub fn handle_workspace_refresh(state: &Arc<Mutex<DaemonState>>) {
-    // Debounce: Check if enough time has passed since the last workspace change
-    let now = Instant::now();
-    let should_process = if let Ok(mut s) = state.lock() {
-        if let Some(last_change) = s.last_workspace_change {
-            if now.duration_since(last_change) < Duration::from_millis(100) {
-                false // Debounce - skip this event
-            } else {
-                s.last_workspace_change = Some(now);
-                true
-            }
-        } else {
-            s.last_workspace_change = Some(now);
-            true
-        }
-    } else {
-        return;
-    };
-
-    if !should_process {
-        return; // Event was debounced
-    }
-
-    // Small delay to let aerospace settle its internal state
-    thread::sleep(Duration::from_millis(10));
-
-    // Get all unique display IDs to determine if we're on single or multi-monitor setup
-    let all_displays: HashSet<u32> = {
-        let temp_infos = aerospace::get_workspace_infos(false);
-        temp_infos.values().map(|info| info.display_id).collect()
-    };
-    let is_single_monitor = all_displays.len() == 1;
-
-    // Show all windows on multiple monitors, one icon per app on single monitor
-    let mut infos = aerospace::get_workspace_infos(!is_single_monitor);
-    
-    // Manual display mapping: swap display 2 with display 3
-    for info in infos.values_mut() {
-        if info.display_id == 2 {
-            info.display_id = 3;
-        } else if info.display_id == 3 {
-            info.display_id = 2;
-        }
-    }
-
-    // Get the set of current workspaces
-    let current_workspaces: HashSet<String> = infos.keys().cloned().collect();
-
-    // Get previous workspaces, config, and update state
-    let (previous_workspaces, config) = if let Ok(mut s) = state.lock() {
-        let prev = s.previous_workspaces.clone();
-        let cfg = s.config.clone();
-        s.previous_workspaces = current_workspaces.clone();
-        (prev, cfg)
-    } else {
-        return;
-    };
-
+/// Build the per-display batches that render `infos`. The items must already
+/// exist (see `workspace_items_batch`).
+fn workspace_batches(
+    infos: &HashMap<String, aerospace::WorkspaceInfo>,
+    config: &crate::config::Config,
+) -> HashMap<u32, SketchybarBatch> {
     // Generate gradient colors from border_active_color (10 steps)
-    let gradient_colors = get_workspace_gradient_colors(&config);
-
-    // Find workspaces that need to be cleared
-    let workspaces_to_clear: HashSet<String> = previous_workspaces
-        .difference(&current_workspaces)
-        .cloned()
-        .collect();
+    let gradient_colors = get_workspace_gradient_colors(config);
 
     // Create a batch per display
     let mut batches: HashMap<u32, SketchybarBatch> = HashMap::new();
 
-    // Clear workspaces that are no longer in aerospace's list
-    for ws_id in workspaces_to_clear {
-        let item_name = format!("workspace.{}", ws_id);
-        for display_id in &all_displays {
-            let batch = batches.entry(*display_id).or_insert_with(SketchybarBatch::new);
-            batch.set(&item_name, &[
-                ("drawing", "off"),
-                ("display", &display_id.to_string()),
-            ]);
-        }
-    }
-
-    // Sort workspaces by ID to get consistent bar order
-    let mut sorted_ws_ids: Vec<&String> = infos.keys().collect();
-    sorted_ws_ids.sort();
+    // Natural (or configured) order, matching the bar order set by `workspace_items_batch`
+    let sorted_ws_ids = ordering::sort_workspaces(infos.keys(), &config.workspace_order);
 
     // Process each workspace in sorted order (matching bar position)
     for (position, ws_id) in sorted_ws_ids.iter().enumerate() {
@@ -510,7 +604,7 @@ pub fn handle_workspace_refresh(state: &Arc<Mutex<DaemonState>>) {
         let display_id = info.display_id;
         let item_name = format!("workspace.{}", ws_id);
         let display_str = display_id.to_string();
-        let batch = batches.entry(display_id).or_insert_with(SketchybarBatch::new);
+        let batch = batches.entry(display_id).or_default();
 
         // Determine colors and states
         let label_color = if is_focused {
@@ -553,31 +647,583 @@ pub fn handle_workspace_refresh(state: &Arc<Mutex<DaemonState>>) {
         batch.set(&item_name, &settings_refs);
     }
 
-    // Execute all batches
-    for (display_id, batch) in batches {
-        if let Err(e) = batch.execute() {
-            eprintln!("Failed to update workspaces on display {}: {}", display_id, e);
+    batches
+}
+
+/// Add items for workspaces that appeared since last time (styled with
+/// `workspace_style`), remove items for workspaces that are gone, hide the
+/// items of workspaces without windows, and reorder them all to match their
+/// natural (or configured) order.
+///
+/// `workspaces` is every workspace aerospace has, `infos` the ones to show.
+/// With `restyle` every item is removed and added again, so style keys that
+/// were dropped from the config don't linger.
+fn workspace_items_batch(
+    workspaces: &HashSet<String>,
+    infos: &HashMap<String, aerospace::WorkspaceInfo>,
+    previous_workspaces: &HashSet<String>,
+    restyle: bool,
+    config: &crate::config::Config,
+) -> SketchybarBatch {
+    let mut batch = SketchybarBatch::new();
+
+    let kept: HashSet<&String> = if restyle {
+        HashSet::new()
+    } else {
+        previous_workspaces.intersection(workspaces).collect()
+    };
+    let gone: HashSet<&String> = previous_workspaces
+        .iter()
+        .filter(|ws_id| !kept.contains(ws_id))
+        .collect();
+    for ws_id in ordering::sort_workspaces(gone, &config.workspace_order) {
+        batch.remove(&format!("workspace.{}", ws_id));
+    }
+
+    let sorted_ws_ids = ordering::sort_workspaces(workspaces, &config.workspace_order);
+    for ws_id in sorted_ws_ids.iter().filter(|ws_id| !kept.contains(*ws_id)) {
+        let item_name = format!("workspace.{}", ws_id);
+        let style: Vec<(&str, String)> = config
+            .workspace_style
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.replace("{workspace}", ws_id)))
+            .collect();
+        let style_refs: Vec<(&str, &str)> = style.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        batch.add(&item_name, "left").set(&item_name, &style_refs);
+    }
+    for ws_id in sorted_ws_ids.iter().filter(|ws_id| !infos.contains_key(**ws_id)) {
+        batch.set(&format!("workspace.{}", ws_id), &[("drawing", "off")]);
+    }
+
+    let mut items: Vec<String> = sorted_ws_ids
+        .iter()
+        .map(|ws_id| format!("workspace.{}", ws_id))
+        .collect();
+    if !items.is_empty() {
+        if !config.workspace_anchor.is_empty() {
+            items.push(config.workspace_anchor.clone());
         }
+        let items: Vec<&str> = items.iter().map(String::as_str).collect();
+        batch.reorder(&items);
+    }
+    batch
+}
+
+/// Remove workspace items left over from an earlier run, so every workspace
+/// item on the bar is one this run added
+pub fn remove_workspace_items() -> HandlerResult {
+    let mut batch = SketchybarBatch::new();
+    batch.remove("/workspace\\..*/");
+    batch
+        .execute()
+        .map_err(|e| format!("Failed to remove old workspace items: {}", e))
+}
+
+/// The machine or its display is going to sleep: hold every timed job,
+/// the config watcher included
+pub fn handle_sleep(state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    scheduler(state)?.suspend();
+    Ok(())
+}
+
+/// The machine or its display woke up: resume the timed jobs, put the clock
+/// back on the minute and re-render everything
+pub fn handle_system_wake(state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    scheduler(state)?.wake();
+    refresh_all(state)
+}
+
+fn scheduler(state: &Arc<Mutex<DaemonState>>) -> Result<Arc<Scheduler>, String> {
+    state
+        .lock()
+        .map(|s| Arc::clone(&s.scheduler))
+        .map_err(|_| "Daemon state lock poisoned".to_string())
+}
+
+/// Re-render every item (after waking from sleep or reloading the config).
+/// The timed jobs all run once, right away, and restart their intervals;
+/// event-driven items (workspaces, volume and the front app) are refreshed
+/// here.
+pub fn refresh_all(state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    scheduler(state)?.trigger_all();
+    // Forget the front app so it is drawn even if it hasn't changed
+    if let Ok(mut s) = state.lock() {
+        s.front_app.clear();
+    }
+    let errors: Vec<String> = [
+        handle_workspace_refresh(state),
+        handle_volume_refresh(None, state),
+        handle_focus_refresh(None, state),
+    ]
+    .into_iter()
+    .filter_map(Result::err)
+    .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
     }
+}
+
+/// Schedule a workspace refresh. Bursts of events are coalesced so that a
+/// single refresh runs once the burst has settled, after its final event.
+pub fn handle_workspace_refresh(state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    let runner = state
+        .lock()
+        .map(|s| Arc::clone(&s.workspace_refresh))
+        .map_err(|_| "Daemon state lock poisoned".to_string())?;
+
+    let state = Arc::clone(state);
+    runner.trigger(move || {
+        if let Err(e) = refresh_workspaces(&state) {
+            eprintln!("{}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Query aerospace and render every workspace item right away
+pub fn refresh_workspaces(state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    let display_map = match state.lock() {
+        Ok(s) => s.config.display_map.clone(),
+        Err(_) => return Err("Daemon state lock poisoned".to_string()),
+    };
+
+    // Get all unique display IDs to determine if we're on single or multi-monitor setup
+    let all_displays: HashSet<u32> = {
+        let mut temp_infos = aerospace::get_workspace_infos(false);
+        aerospace::apply_display_map(&mut temp_infos, &display_map);
+        temp_infos.values().map(|info| info.display_id).collect()
+    };
+    let is_single_monitor = all_displays.len() == 1;
+
+    // Show all windows on multiple monitors, one icon per app on single monitor
+    let mut infos = aerospace::get_workspace_infos(!is_single_monitor);
+    aerospace::apply_display_map(&mut infos, &display_map);
+
+    // Workspaces without windows keep their (hidden) items, so opening or
+    // closing a window doesn't add or remove an item
+    let workspaces: HashSet<String> = aerospace::list_workspaces()
+        .into_iter()
+        .chain(infos.keys().cloned())
+        .collect();
+    let result = render_workspaces(state, &workspaces, &infos, |batch| batch.execute());
 
     // Update borders active color
+    let border_color = match state.lock() {
+        Ok(s) => s.config.border_active_color.clone(),
+        Err(_) => return Err("Daemon state lock poisoned".to_string()),
+    };
     std::thread::sleep(std::time::Duration::from_millis(40));
-    let border_arg = format!("active_color={}", config.border_active_color);
+    let border_arg = format!("active_color={}", border_color);
     if let Err(e) = Command::new("/opt/homebrew/bin/borders")
         .arg(&border_arg)
         .status()
     {
         eprintln!("Failed to update borders color: {}", e);
     }
+
+    result
+}
+
+/// Bring the workspace items in line with `workspaces` and render `infos`
+/// on them, sending each batch through `execute`
+fn render_workspaces(
+    state: &Arc<Mutex<DaemonState>>,
+    workspaces: &HashSet<String>,
+    infos: &HashMap<String, aerospace::WorkspaceInfo>,
+    mut execute: impl FnMut(&SketchybarBatch) -> Result<(), std::io::Error>,
+) -> HandlerResult {
+    // Get previous workspaces and config
+    let (previous_workspaces, restyle, config) = if let Ok(s) = state.lock() {
+        (s.previous_workspaces.clone(), s.restyle_workspaces, s.config.clone())
+    } else {
+        return Err("Daemon state lock poisoned".to_string());
+    };
+
+    // Add, remove and reorder items first so the per-display updates land on existing items
+    let items = workspace_items_batch(workspaces, infos, &previous_workspaces, restyle, &config);
+    if let Err(e) = execute(&items) {
+        return Err(format!("Failed to add or remove workspace items: {}", e));
+    }
+
+    // Only remember the workspaces once their items exist, so a failed add is retried
+    if let Ok(mut s) = state.lock() {
+        s.previous_workspaces = workspaces.clone();
+        s.restyle_workspaces = false;
+    }
+
+    // Execute all batches
+    let mut errors = Vec::new();
+    for (display_id, batch) in workspace_batches(infos, &config) {
+        if let Err(e) = execute(&batch) {
+            errors.push(format!("Failed to update workspaces on display {}: {}", display_id, e));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
 }
 
 /// Focus a workspace via aerospace and launch its configured app if not already running.
-/// Runs in a background thread so the daemon doesn't block on the app launch.
-pub fn handle_aerospace_focus(workspace: String, state: &Arc<Mutex<DaemonState>>) {
+/// The focus itself runs synchronously so its outcome can be reported to the caller;
+/// the app launch and bar refresh run in a background thread so the daemon doesn't block.
+pub fn handle_aerospace_focus(workspace: String, state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    if !aerospace_focus::aerospace_focus(&workspace) {
+        return Err(format!("aerospace workspace '{}' failed", workspace));
+    }
+
     let state = Arc::clone(state);
     thread::spawn(move || {
-        aerospace_focus::focus_workspace(&workspace);
-        handle_workspace_refresh(&state);
+        aerospace_focus::ensure_workspace_app(&workspace);
+        if let Err(e) = handle_workspace_refresh(&state) {
+            eprintln!("{}", e);
+        }
     });
+
+    Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aerospace::WorkspaceInfo;
+    use crate::config::Config;
+    use crate::sink::RecordingSink;
+
+    fn workspace(id: &str, apps: &[&str], icons: &str, is_focused: bool, display_id: u32) -> WorkspaceInfo {
+        WorkspaceInfo {
+            id: id.to_string(),
+            apps: apps.iter().map(|s| s.to_string()).collect(),
+            icons: icons.to_string(),
+            is_focused,
+            display_id,
+            monitor_name: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_battery_batch_args() {
+        let sink = RecordingSink::new();
+        let info = providers::BatteryInfo { percentage: 50, ..Default::default() };
+        battery_batch(&info, &Config::default(), |_| false).send_to(&sink).unwrap();
+
+        assert_eq!(sink.args(), vec![
+            "--set",
+            "battery",
+            "icon=\u{f12a2}",
+            "icon.color=0xffF5EEE2",
+            "label.color=0xffF5EEE2",
+            "label=50%",
+        ]);
+    }
+
+    #[test]
+    fn test_battery_alert_flashes_and_restores_colors() {
+        let info = providers::BatteryInfo { percentage: 18, ..Default::default() };
+        let args = battery_alert_batch(Alert::Warning, &info, &Config::default()).args();
+        let flash = ["--animate", "tanh", "10", "--set", "battery", "icon.color=0xfffe8019", "label.color=0xfffe8019"];
+        let restore = ["--animate", "tanh", "10", "--set", "battery", "icon.color=0xffF5EEE2", "label.color=0xffF5EEE2"];
+        assert_eq!(args, [flash, restore].concat().repeat(3));
+
+        let args = battery_alert_batch(Alert::Critical, &info, &Config::default()).args();
+        assert!(args.contains(&"icon.color=0xfffb4934".to_string()));
+    }
+
+    fn battery_args(time_in: Placement, added: &[&str]) -> Vec<String> {
+        let info = providers::BatteryInfo {
+            percentage: 85,
+            state: providers::ChargeState::Discharging,
+            minutes_remaining: Some(332),
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.battery.time_in = time_in;
+        battery_batch(&info, &config, |item| added.contains(&item)).args()
+    }
+
+    #[test]
+    fn test_battery_time_in_label() {
+        let args = battery_args(Placement::Label, &["battery.time"]);
+        assert!(args.contains(&"label=85% · 5:32".to_string()));
+        assert!(args.ends_with(&["--remove".to_string(), "battery.time".to_string()]));
+    }
+
+    #[test]
+    fn test_battery_time_in_popup() {
+        let args = battery_args(Placement::Popup, &[]);
+        assert!(args.contains(&"label=85%".to_string()));
+        assert!(args.ends_with(&[
+            "--add".to_string(), "item".to_string(), "battery.time".to_string(), "popup.battery".to_string(),
+            "--set".to_string(), "battery.time".to_string(), "icon.drawing=off".to_string(),
+            "label=Discharging · 5:32 remaining".to_string(),
+        ]));
+    }
+
+    fn clock_args(zones_in: Placement, zones: &[&str], added: &[&str]) -> Vec<String> {
+        let clock = providers::ClockText {
+            time: "14/03 13:14".to_string(),
+            zones: zones.iter().map(|z| z.to_string()).collect(),
+        };
+        let config = crate::config::ClockConfig { zones_in, ..Default::default() };
+        clock_batch(&clock, &config, |item| added.contains(&item)).args()
+    }
+
+    #[test]
+    fn test_clock_zones_in_label() {
+        assert_eq!(
+            clock_args(Placement::Label, &["NYC 09:14", "BLR 18:44"], &[]),
+            vec!["--set", "clock", "label=14/03 13:14 · NYC 09:14 · BLR 18:44"]
+        );
+        // Switched from popup mode: the popup items go
+        assert_eq!(
+            clock_args(Placement::Label, &[], &["clock.zone.0"]),
+            vec!["--set", "clock", "label=14/03 13:14", "--remove", "clock.zone.0"]
+        );
+    }
+
+    #[test]
+    fn test_clock_zones_in_popup() {
+        assert_eq!(
+            clock_args(Placement::Popup, &["NYC 09:14"], &["clock.zone.0", "clock.zone.1"]),
+            vec![
+                "--set", "clock", "label=14/03 13:14",
+                "--add", "item", "clock.zone.0", "popup.clock",
+                "--set", "clock.zone.0", "icon.drawing=off", "label=NYC 09:14",
+                "--remove", "clock.zone.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_power_source_switches_profile() {
+        let mut state = DaemonState::new(Config::default());
+        assert!(!state.set_power(PowerSource::Ac));
+        assert!(state.set_power(PowerSource::Battery));
+        assert!(!state.set_power(PowerSource::Battery));
+        assert_eq!(state.snapshot()["power"], "battery");
+
+        // A reload keeps the current profile
+        state.replace_config(Config::default());
+        assert_eq!(state.power, PowerSource::Battery);
+    }
+
+    #[test]
+    fn test_replace_config_restyles_only_when_style_changes() {
+        let mut state = DaemonState::new(Config::default());
+        state.previous_workspaces.insert("1".to_string());
+
+        let config = Config {
+            battery_normal_color: "0xff000000".to_string(),
+            ..Config::default()
+        };
+        state.replace_config(config);
+        assert_eq!(state.config.battery_normal_color, "0xff000000");
+        assert!(state.previous_workspaces.contains("1"));
+
+        assert!(!state.restyle_workspaces);
+
+        let mut config = Config::default();
+        config.workspace_style.insert("padding_right".to_string(), "4".to_string());
+        state.replace_config(config);
+        assert!(state.previous_workspaces.contains("1"));
+        assert!(state.restyle_workspaces);
+    }
+
+    #[test]
+    fn test_workspace_batches_args() {
+        let sink = RecordingSink::new();
+        let infos: HashMap<String, WorkspaceInfo> = [
+            ("1".to_string(), workspace("1", &["Safari"], ":safari:", true, 1)),
+            ("2".to_string(), workspace("2", &[], "", false, 1)),
+        ]
+        .into_iter()
+        .collect();
+
+        let batches = workspace_batches(&infos, &Config::default());
+        assert_eq!(batches.len(), 1);
+        batches[&1].send_to(&sink).unwrap();
+
+        assert_eq!(sink.args(), vec![
+            "--set", "workspace.1",
+            "label=[1]",
+            "label.color=0xff1d2021",
+            "icon=:safari:",
+            "icon.color=0xff1d2021",
+            "icon.drawing=on",
+            "drawing=on",
+            "background.drawing=on",
+            "display=1",
+            "background.color=0xffbb60cd",
+            "--set", "workspace.2",
+            "label=\u{f444} [2]",
+            "label.color=0xffF5EEE2",
+            "icon=",
+            "icon.color=0xffF5EEE2",
+            "icon.drawing=off",
+            "drawing=on",
+            "background.drawing=off",
+            "display=1",
+        ]);
+    }
+
+    #[test]
+    fn test_workspace_batches_split_by_display() {
+        let infos: HashMap<String, WorkspaceInfo> = [
+            ("1".to_string(), workspace("1", &["Safari"], ":safari:", false, 1)),
+            ("2".to_string(), workspace("2", &["Mail"], ":mail:", true, 2)),
+        ]
+        .into_iter()
+        .collect();
+
+        let batches = workspace_batches(&infos, &Config::default());
+
+        let sink = RecordingSink::new();
+        batches[&2].send_to(&sink).unwrap();
+        let args = sink.args();
+        assert_eq!(&args[..2], ["--set", "workspace.2"]);
+        assert!(args.contains(&"display=2".to_string()));
+        assert!(!args.contains(&"workspace.1".to_string()));
+    }
+
+    #[test]
+    fn test_workspace_order_drives_gradient_and_reorder() {
+        let infos: HashMap<String, WorkspaceInfo> = [
+            ("10".to_string(), workspace("10", &[], "", true, 1)),
+            ("2".to_string(), workspace("2", &[], "", false, 1)),
+            ("1".to_string(), workspace("1", &[], "", false, 1)),
+        ]
+        .into_iter()
+        .collect();
+        let existing: HashSet<String> = infos.keys().cloned().collect();
+        let mut config = Config::default();
+        let gradient = get_workspace_gradient_colors(&config);
+
+        // Natural order: "10" is third, not second
+        let sink = RecordingSink::new();
+        workspace_batches(&infos, &config)[&1].send_to(&sink).unwrap();
+        let bg = format!("background.color={}", gradient[2]);
+        assert!(sink.args().contains(&bg), "{:?}", sink.args());
+        assert_eq!(workspace_items_batch(&existing, &infos, &existing, false, &config).args(), vec![
+            "--reorder", "workspace.1", "workspace.2", "workspace.10", "front_app",
+        ]);
+
+        // An explicit order puts "10" first
+        config.workspace_order = vec!["10".to_string()];
+        let sink = RecordingSink::new();
+        workspace_batches(&infos, &config)[&1].send_to(&sink).unwrap();
+        let bg = format!("background.color={}", gradient[0]);
+        assert!(sink.args().contains(&bg), "{:?}", sink.args());
+        assert_eq!(workspace_items_batch(&existing, &infos, &existing, false, &config).args(), vec![
+            "--reorder", "workspace.10", "workspace.1", "workspace.2", "front_app",
+        ]);
+    }
+
+    #[test]
+    fn test_workspace_items_added_and_removed() {
+        let infos: HashMap<String, WorkspaceInfo> = [
+            ("1".to_string(), workspace("1", &["Safari"], ":safari:", true, 1)),
+            ("web".to_string(), workspace("web", &[], "", false, 1)),
+            ("2".to_string(), workspace("2", &[], "", false, 1)),
+        ]
+        .into_iter()
+        .collect();
+        let previous: HashSet<String> = ["1".to_string(), "3".to_string()].into_iter().collect();
+        let config = Config {
+            workspace_style: [
+                ("padding_right".to_string(), "11".to_string()),
+                ("click_script".to_string(), "aerospace workspace {workspace}".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            workspace_anchor: String::new(),
+            ..Config::default()
+        };
+
+        let workspaces: HashSet<String> = infos.keys().cloned().collect();
+        assert_eq!(workspace_items_batch(&workspaces, &infos, &previous, false, &config).args(), vec![
+            "--remove", "workspace.3",
+            "--add", "item", "workspace.2", "left",
+            "--set", "workspace.2",
+            "click_script=aerospace workspace 2",
+            "padding_right=11",
+            "--add", "item", "workspace.web", "left",
+            "--set", "workspace.web",
+            "click_script=aerospace workspace web",
+            "padding_right=11",
+            "--reorder", "workspace.1", "workspace.2", "workspace.web",
+        ]);
+    }
+
+    #[test]
+    fn test_no_workspaces_removes_everything_without_reorder() {
+        let previous: HashSet<String> = ["1".to_string()].into_iter().collect();
+        let args = workspace_items_batch(&HashSet::new(), &HashMap::new(), &previous, false, &Config::default()).args();
+        assert_eq!(args, vec!["--remove", "workspace.1"]);
+    }
+
+    #[test]
+    fn test_workspace_without_windows_keeps_its_item() {
+        let infos: HashMap<String, WorkspaceInfo> = [("1".to_string(), workspace("1", &["Safari"], ":safari:", true, 1))]
+            .into_iter()
+            .collect();
+        let workspaces: HashSet<String> = ["1".to_string(), "2".to_string()].into_iter().collect();
+        assert_eq!(workspace_items_batch(&workspaces, &infos, &workspaces, false, &Config::default()).args(), vec![
+            "--set", "workspace.2", "drawing=off",
+            "--reorder", "workspace.1", "workspace.2", "front_app",
+        ]);
+    }
+
+    #[test]
+    fn test_reload_then_refresh_recreates_workspace_items() {
+        let mut config = Config {
+            workspace_style: [
+                ("padding_right".to_string(), "11".to_string()),
+                ("label.y_offset".to_string(), "1".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            workspace_anchor: String::new(),
+            ..Config::default()
+        };
+        let state = Arc::new(Mutex::new(DaemonState::new(config.clone())));
+        let infos: HashMap<String, WorkspaceInfo> = [
+            ("1".to_string(), workspace("1", &[], "", true, 1)),
+            ("2".to_string(), workspace("2", &[], "", false, 1)),
+        ]
+        .into_iter()
+        .collect();
+        let workspaces: HashSet<String> = infos.keys().cloned().collect();
+        let mut cache = render::RenderCache::new();
+        let refresh = |workspaces: &HashSet<String>, cache: &mut render::RenderCache| {
+            let sink = RecordingSink::new();
+            render_workspaces(&state, workspaces, &infos, |batch| batch.send_cached(&sink, cache)).unwrap();
+            sink.args()
+        };
+        refresh(&workspaces, &mut cache);
+
+        // Workspace 2 disappears while the new style is waiting to be applied
+        config.workspace_style.remove("label.y_offset");
+        config.workspace_style.insert("padding_right".to_string(), "4".to_string());
+        state.lock().unwrap().replace_config(config);
+        let remaining: HashSet<String> = ["1".to_string()].into_iter().collect();
+        let args = refresh(&remaining, &mut cache);
+        assert_eq!(args[..10], [
+            "--remove", "workspace.1",
+            "--remove", "workspace.2",
+            "--add", "item", "workspace.1", "left",
+            "--set", "workspace.1",
+        ]);
+        assert!(args.contains(&"padding_right=4".to_string()));
+        assert!(!args.contains(&"label.y_offset=1".to_string()));
+        assert!(args.contains(&"label=\u{f444} [1]".to_string()), "{:?}", args);
+
+        // Once restyled, the next refresh leaves the items alone
+        assert_eq!(refresh(&remaining, &mut cache), Vec::<String>::new());
+        assert_eq!(state.lock().unwrap().previous_workspaces, remaining);
+    }
+}
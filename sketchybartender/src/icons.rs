@@ -0,0 +1,128 @@
+//! Battery and volume glyphs
+//!
+//! Each set has a charging glyph and a ladder of battery levels, plus a mute
+//! glyph and a ladder of volume levels, both from empty to full. The battery
+//! can use fewer steps than its set has; levels are then picked evenly along
+//! the ladder.
+
+use serde::{Deserialize, Serialize};
+
+/// Which glyphs the battery and volume items use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconSet {
+    /// Nerd Font glyphs (the stock sketchybarrc fonts)
+    #[default]
+    NerdFont,
+    /// SF Symbols; needs an SF Pro `icon.font` on the items
+    SfSymbols,
+    /// Plain ASCII, for any font
+    Text,
+}
+
+struct Glyphs {
+    charging: &'static str,
+    battery: &'static [&'static str],
+    muted: &'static str,
+    volume: &'static [&'static str],
+}
+
+const NERD_FONT: Glyphs = Glyphs {
+    charging: "\u{f0e7}", // nf-fa-bolt
+    // nf-md-battery_outline, _low, _medium, _high
+    battery: &["\u{f008e}", "\u{f12a1}", "\u{f12a2}", "\u{f12a3}"],
+    muted: "\u{f0581}", // nf-md-volume_off
+    // nf-fa-battery_0 to _4, read as level bars
+    volume: &["\u{f244}", "\u{f243}", "\u{f242}", "\u{f241}", "\u{f240}"],
+};
+
+const SF_SYMBOLS: Glyphs = Glyphs {
+    charging: "\u{10088b}", // battery.100.bolt
+    // battery.0, .25, .50, .75, .100
+    battery: &["\u{1006ea}", "\u{1006e9}", "\u{100eb6}", "\u{100eb8}", "\u{1006e8}"],
+    muted: "\u{1002a3}", // speaker.slash.fill
+    // speaker.fill, speaker.wave.1.fill to .3.fill
+    volume: &["\u{1002a1}", "\u{1002a5}", "\u{1002a7}", "\u{1002a9}"],
+};
+
+const TEXT: Glyphs = Glyphs {
+    charging: "[ ++ ]",
+    battery: &["[    ]", "[|   ]", "[||  ]", "[||| ]", "[||||]"],
+    muted: "[ x ]",
+    volume: &["[   ]", "[|  ]", "[|| ]", "[|||]"],
+};
+
+impl IconSet {
+    fn glyphs(self) -> &'static Glyphs {
+        match self {
+            IconSet::NerdFont => &NERD_FONT,
+            IconSet::SfSymbols => &SF_SYMBOLS,
+            IconSet::Text => &TEXT,
+        }
+    }
+
+    /// Most battery steps this set can show
+    pub fn battery_steps(self) -> usize {
+        self.glyphs().battery.len()
+    }
+
+    pub fn battery(self, percentage: u8, charging: bool, steps: usize) -> &'static str {
+        let glyphs = self.glyphs();
+        if charging {
+            glyphs.charging
+        } else {
+            level(glyphs.battery, percentage, steps)
+        }
+    }
+
+    pub fn volume(self, percentage: u8, muted: bool) -> &'static str {
+        let glyphs = self.glyphs();
+        if muted {
+            glyphs.muted
+        } else {
+            level(glyphs.volume, percentage, glyphs.volume.len())
+        }
+    }
+}
+
+/// The glyph for `percentage` when `ladder` is cut down to `steps` evenly
+/// spaced levels, each covering an equal share of 0–100%
+fn level(ladder: &'static [&'static str], percentage: u8, steps: usize) -> &'static str {
+    let steps = steps.clamp(2, ladder.len());
+    let step = (percentage as usize * steps / 100).min(steps - 1);
+    ladder[(step * (ladder.len() - 1) + (steps - 1) / 2) / (steps - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_steps() {
+        let icons = |steps| -> Vec<&str> {
+            [0, 30, 55, 70, 100]
+                .into_iter()
+                .map(|p| IconSet::Text.battery(p, false, steps))
+                .collect()
+        };
+        assert_eq!(icons(5), ["[    ]", "[|   ]", "[||  ]", "[||| ]", "[||||]"]);
+        assert_eq!(icons(3), ["[    ]", "[    ]", "[||  ]", "[||||]", "[||||]"]);
+        assert_eq!(icons(2), ["[    ]", "[    ]", "[||||]", "[||||]", "[||||]"]);
+        // More steps than the set has, or too few, are clamped
+        assert_eq!(icons(10), icons(5));
+        assert_eq!(icons(0), icons(2));
+        assert_eq!(IconSet::Text.battery(40, true, 5), "[ ++ ]");
+    }
+
+    #[test]
+    fn test_every_set_covers_the_range() {
+        for set in [IconSet::NerdFont, IconSet::SfSymbols, IconSet::Text] {
+            let glyphs = set.glyphs();
+            assert_eq!(set.battery(0, false, set.battery_steps()), glyphs.battery[0]);
+            assert_eq!(set.battery(100, false, set.battery_steps()), *glyphs.battery.last().unwrap());
+            assert_eq!(set.volume(0, false), glyphs.volume[0]);
+            assert_eq!(set.volume(100, false), *glyphs.volume.last().unwrap());
+            assert_eq!(set.volume(100, true), glyphs.muted);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Layered configuration
+//!
+//! The effective config is merged from, lowest precedence first:
+//! 1. the built-in defaults
+//! 2. the user file, `sketchybartender.{toml,json}`
+//! 3. a per-host file, `sketchybartender.<hostname>.{toml,json}`, where the
+//!    hostname is cut at the first dot (`Ollis-MacBook.local` → `Ollis-MacBook`)
+//! 4. `SKETCHYBARTENDER_*` environment variables
+//!
+//! Objects are merged key by key; anything else replaces what's below it.
+//! Every value remembers which layer set it so the merged result can be
+//! printed with its origin.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::path::PathBuf;
+
+use serde_json::{Map, Value};
+
+/// Prefix of environment variables that override config keys
+pub const ENV_PREFIX: &str = "SKETCHYBARTENDER_";
+
+/// Where a config value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+/// Short name of this machine, as used in per-host file names
+pub fn hostname() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    // Not guaranteed to be terminated if the name was truncated
+    buf[buf.len() - 1] = 0;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+    let short = name.split('.').next().unwrap_or_default();
+    (!short.is_empty()).then(|| short.to_string())
+}
+
+/// One layer per `SKETCHYBARTENDER_*` variable, with the variable's name.
+///
+/// The rest of the name is the key, lowercased, with `__` between nested
+/// keys: `SKETCHYBARTENDER_INTERVALS__BATTERY=60`. Values are read as JSON
+/// when they parse (numbers, booleans, lists, objects) and as plain strings
+/// otherwise.
+pub fn env_layers(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, Value)> {
+    let mut layers = Vec::new();
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_lowercase();
+        let keys: Vec<&str> = key.split("__").collect();
+        if keys.iter().any(|k| k.is_empty()) {
+            continue;
+        }
+        let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+        let layer = keys.iter().rev().fold(value, |value, key| {
+            Value::Object(Map::from_iter([(key.to_string(), value)]))
+        });
+        layers.push((name, layer));
+    }
+    // Variables come in no particular order; make overlapping ones predictable
+    layers.sort_by(|a, b| a.0.cmp(&b.0));
+    layers
+}
+
+/// Config values merged from several layers, with the layer each came from
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub value: Value,
+    /// Dotted key path → the layer that last set it
+    origins: BTreeMap<String, Source>,
+}
+
+impl Merged {
+    pub fn new(defaults: Value) -> Self {
+        Self {
+            value: defaults,
+            origins: BTreeMap::new(),
+        }
+    }
+
+    /// Merge `layer` over what's there so far
+    pub fn apply(&mut self, layer: &Value, source: &Source) {
+        merge(&mut self.value, layer, "", &mut |path| {
+            self.origins.retain(|p, _| !is_within(p, path));
+            self.origins.insert(path.to_string(), source.clone());
+        });
+    }
+
+    /// The layer that set `path`, or the one that set the closest enclosing key
+    pub fn source_of(&self, path: &str) -> &Source {
+        let mut path = path.split('[').next().unwrap_or_default();
+        loop {
+            if let Some(source) = self.origins.get(path) {
+                return source;
+            }
+            match path.rsplit_once('.') {
+                Some((parent, _)) => path = parent,
+                None => return &Source::Default,
+            }
+        }
+    }
+
+    /// Every leaf of `effective` as `(path, value, source)`. Values that
+    /// differ from the merged ones (because they were reset to their default)
+    /// are attributed to the defaults.
+    pub fn describe(&self, effective: &Value) -> Vec<(String, String, Source)> {
+        let mut lines = Vec::new();
+        leaves(effective, &mut Vec::new(), &mut |keys, value| {
+            let path = keys.join(".");
+            let pointer: String = keys
+                .iter()
+                .map(|k| format!("/{}", k.replace('~', "~0").replace('/', "~1")))
+                .collect();
+            let source = if self.value.pointer(&pointer) == Some(value) {
+                self.source_of(&path).clone()
+            } else {
+                Source::Default
+            };
+            lines.push((path, value.to_string(), source));
+        });
+        lines
+    }
+}
+
+/// `path` is `parent` or a key below it
+fn is_within(path: &str, parent: &str) -> bool {
+    path == parent || path.strip_prefix(parent).is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Merge `layer` into `base`, calling `set` with the path of every value replaced
+fn merge(base: &mut Value, layer: &Value, prefix: &str, set: &mut impl FnMut(&str)) {
+    let (Some(base), Some(layer)) = (base.as_object_mut(), layer.as_object()) else {
+        return;
+    };
+    for (key, value) in layer {
+        let path = format!("{}{}", prefix, key);
+        match base.get_mut(key) {
+            Some(existing) if existing.is_object() && value.is_object() => {
+                merge(existing, value, &format!("{}.", path), set);
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+                set(&path);
+            }
+        }
+    }
+}
+
+/// Call `f` with every non-object value in `value` and the keys leading to it
+fn leaves<'a>(value: &'a Value, keys: &mut Vec<&'a str>, f: &mut impl FnMut(&[&str], &Value)) {
+    match value.as_object() {
+        Some(object) if !object.is_empty() || keys.is_empty() => {
+            for (key, value) in object {
+                keys.push(key);
+                leaves(value, keys, f);
+                keys.pop();
+            }
+        }
+        _ => f(keys, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn file(name: &str) -> Source {
+        Source::File(PathBuf::from(name))
+    }
+
+    #[test]
+    fn test_later_layers_win_key_by_key() {
+        let mut merged = Merged::new(json!({
+            "sink": "mach",
+            "intervals": {"clock": 15, "battery": 120},
+            "workspace_order": ["1"],
+        }));
+        merged.apply(&json!({"intervals": {"battery": 60}, "workspace_order": ["m", "1"]}), &file("user.json"));
+        merged.apply(&json!({"intervals": {"clock": 30}}), &file("user.laptop.json"));
+
+        assert_eq!(merged.value, json!({
+            "sink": "mach",
+            "intervals": {"clock": 30, "battery": 60},
+            "workspace_order": ["m", "1"],
+        }));
+        assert_eq!(merged.source_of("sink"), &Source::Default);
+        assert_eq!(merged.source_of("intervals.battery"), &file("user.json"));
+        assert_eq!(merged.source_of("intervals.clock"), &file("user.laptop.json"));
+        assert_eq!(merged.source_of("workspace_order[1]"), &file("user.json"));
+    }
+
+    #[test]
+    fn test_replacing_a_section_forgets_its_keys() {
+        let mut merged = Merged::new(json!({"display_map": {}}));
+        merged.apply(&json!({"display_map": {"DELL": 1}}), &file("user.json"));
+        merged.apply(&json!({"display_map": "oops"}), &file("host.json"));
+        assert_eq!(merged.source_of("display_map.DELL"), &file("host.json"));
+    }
+
+    #[test]
+    fn test_env_layers() {
+        let vars = [
+            ("SKETCHYBARTENDER_SINK", "cli"),
+            ("SKETCHYBARTENDER_INTERVALS__BATTERY", "60"),
+            ("SKETCHYBARTENDER_WORKSPACE_ORDER", r#"["m", "1"]"#),
+            ("SKETCHYBARTENDER_BATTERY_LOW_COLOR", "0xfffb4934"),
+            ("SKETCHYBARTENDER_", "ignored"),
+            ("HOME", "/Users/me"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let mut merged = Merged::new(json!({"intervals": {"clock": 15}}));
+        for (name, layer) in env_layers(vars) {
+            merged.apply(&layer, &Source::Env(name));
+        }
+        assert_eq!(merged.value, json!({
+            "sink": "cli",
+            "intervals": {"clock": 15, "battery": 60},
+            "workspace_order": ["m", "1"],
+            "battery_low_color": "0xfffb4934",
+        }));
+        assert_eq!(
+            merged.source_of("intervals.battery"),
+            &Source::Env("SKETCHYBARTENDER_INTERVALS__BATTERY".to_string())
+        );
+        assert_eq!(merged.source_of("intervals.clock"), &Source::Default);
+    }
+
+    #[test]
+    fn test_describe_lists_leaves_with_sources() {
+        let mut merged = Merged::new(json!({
+            "sink": "mach",
+            "intervals": {"clock": 15},
+            "display_map": {},
+            "workspace_style": {"icon.font": "A"},
+        }));
+        merged.apply(&json!({"intervals": {"clock": 0}}), &file("user.json"));
+        merged.apply(&json!({"workspace_style": {"icon.font": "B"}}), &file("user.json"));
+        merged.apply(&json!({"sink": "cli"}), &Source::Env("SKETCHYBARTENDER_SINK".to_string()));
+
+        // clock was reset to its default after failing validation
+        let effective = json!({
+            "sink": "cli",
+            "intervals": {"clock": 15},
+            "display_map": {},
+            "workspace_style": {"icon.font": "B"},
+        });
+        let lines: Vec<String> = merged
+            .describe(&effective)
+            .into_iter()
+            .map(|(path, value, source)| format!("{} = {} ({})", path, value, source))
+            .collect();
+        assert_eq!(lines, [
+            "display_map = {} (default)",
+            "intervals.clock = 15 (default)",
+            "sink = \"cli\" ($SKETCHYBARTENDER_SINK)",
+            "workspace_style.icon.font = \"B\" (user.json)",
+        ]);
+    }
+
+    #[test]
+    fn test_hostname_is_short() {
+        if let Some(name) = hostname() {
+            assert!(!name.is_empty());
+            assert!(!name.contains('.'));
+        }
+    }
+}
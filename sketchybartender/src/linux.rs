@@ -0,0 +1,245 @@
+//! Linux backends: `/proc/stat`, `/proc/meminfo` and `/sys/class/power_supply`
+//!
+//! Every path is read below a root directory (`/` on a real machine) so the
+//! backends can be pointed at a fixture tree.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::providers::{BatteryInfo, ChargeState, CpuTicks, MemoryInfo, Supply};
+use crate::registry::Provider;
+
+fn read(root: &Path, path: &str) -> Result<String, String> {
+    let path = root.join(path);
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// CPU ticks from the aggregate `cpu` line of `/proc/stat`
+pub struct ProcStat {
+    pub root: PathBuf,
+}
+
+impl Provider for ProcStat {
+    type Snapshot = CpuTicks;
+
+    fn backend(&self) -> &'static str {
+        "/proc/stat"
+    }
+
+    fn read(&mut self) -> Result<CpuTicks, String> {
+        parse_proc_stat(&read(&self.root, "proc/stat")?).ok_or_else(|| "No cpu line in /proc/stat".to_string())
+    }
+}
+
+/// `cpu  user nice system idle iowait irq softirq steal guest guest_nice`.
+/// Waiting on I/O counts as idle; guest time is already part of user and
+/// nice, so it isn't added again.
+pub fn parse_proc_stat(contents: &str) -> Option<CpuTicks> {
+    let line = contents.lines().find(|l| l.split_whitespace().next() == Some("cpu"))?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|f| f.parse().ok())
+        .collect::<Option<_>>()?;
+    if fields.len() < 4 {
+        return None;
+    }
+    let field = |i: usize| fields.get(i).copied().unwrap_or(0);
+    let busy = field(0) + field(1) + field(2) + field(5) + field(6) + field(7);
+    let idle = field(3) + field(4);
+    Some((busy, busy + idle))
+}
+
+/// Memory use from `/proc/meminfo`
+pub struct ProcMeminfo {
+    pub root: PathBuf,
+}
+
+impl Provider for ProcMeminfo {
+    type Snapshot = MemoryInfo;
+
+    fn backend(&self) -> &'static str {
+        "/proc/meminfo"
+    }
+
+    fn read(&mut self) -> Result<MemoryInfo, String> {
+        parse_meminfo(&read(&self.root, "proc/meminfo")?)
+            .ok_or_else(|| "No MemTotal/MemAvailable in /proc/meminfo".to_string())
+    }
+}
+
+/// Used memory is what the kernel doesn't consider available (free memory
+/// plus reclaimable caches), like `free`'s "used" column
+pub fn parse_meminfo(contents: &str) -> Option<MemoryInfo> {
+    let kib = |key: &str| -> Option<u64> {
+        let line = contents.lines().find(|l| l.split(':').next() == Some(key))?;
+        line.split_whitespace().nth(1)?.parse().ok()
+    };
+    let total = kib("MemTotal")?;
+    let available = kib("MemAvailable")?;
+    Some(MemoryInfo {
+        used_bytes: total.saturating_sub(available) * 1024,
+        total_bytes: total * 1024,
+    })
+}
+
+/// Battery charge and power source from `/sys/class/power_supply`
+pub struct PowerSupply {
+    pub root: PathBuf,
+}
+
+impl Provider for PowerSupply {
+    type Snapshot = Option<BatteryInfo>;
+
+    fn backend(&self) -> &'static str {
+        "/sys/class/power_supply"
+    }
+
+    fn read(&mut self) -> Result<Option<BatteryInfo>, String> {
+        let dir = self.root.join("sys/class/power_supply");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // No power supply drivers at all, as in most VMs and containers
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e)),
+        };
+        let mut supplies: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+        // BAT0 before BAT1
+        supplies.sort();
+
+        let attr = |supply: &Path, name: &str| {
+            fs::read_to_string(supply.join(name))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let mut battery = None;
+        let mut mains_online = None;
+        for supply in &supplies {
+            match attr(supply, "type").as_str() {
+                // Skip the batteries of mice and keyboards
+                "Battery" if battery.is_none() && attr(supply, "scope") != "Device" => battery = Some(supply),
+                "Mains" => {
+                    mains_online = Some(mains_online.unwrap_or(false) || attr(supply, "online") == "1");
+                }
+                _ => {}
+            }
+        }
+
+        let Some(battery) = battery else {
+            return Ok(None);
+        };
+        let percentage = attr(battery, "capacity")
+            .parse::<u8>()
+            .map_err(|_| format!("Unreadable capacity in {}", battery.display()))?;
+        let state = match attr(battery, "status").as_str() {
+            "Charging" => ChargeState::Charging,
+            "Discharging" => ChargeState::Discharging,
+            "Full" => ChargeState::Charged,
+            "Not charging" => ChargeState::NotCharging,
+            _ => ChargeState::Unknown,
+        };
+        // Without a mains adapter to ask, a battery that isn't draining is on AC
+        let on_ac = mains_online.unwrap_or(state != ChargeState::Discharging);
+        let number = |name: &str| attr(battery, name).parse::<u64>().ok();
+        Ok(Some(BatteryInfo {
+            percentage: percentage.min(100),
+            state,
+            supply: if on_ac { Supply::Ac } else { Supply::Battery },
+            minutes_remaining: minutes_remaining(state, number),
+        }))
+    }
+}
+
+/// Minutes until empty or full at the current rate. Batteries report either
+/// energy (µWh, µW) or charge (µAh, µA).
+fn minutes_remaining(state: ChargeState, number: impl Fn(&str) -> Option<u64>) -> Option<u32> {
+    let (now, full, rate) = match number("power_now") {
+        Some(rate) => (number("energy_now")?, number("energy_full")?, rate),
+        None => (number("charge_now")?, number("charge_full")?, number("current_now")?),
+    };
+    let left = match state {
+        ChargeState::Discharging => now,
+        ChargeState::Charging | ChargeState::FinishingCharge => full.saturating_sub(now),
+        _ => return None,
+    };
+    (rate > 0).then(|| (left * 60 / rate) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let stat = "cpu  4705 150 1120 16250 520 20 60 5 0 0\n\
+                    cpu0 2000 75 500 8000 260 10 30 2 0 0\n\
+                    intr 12345 0 0\n";
+        let busy = 4705 + 150 + 1120 + 20 + 60 + 5;
+        assert_eq!(parse_proc_stat(stat), Some((busy, busy + 16250 + 520)));
+        // Older kernels have only four columns
+        assert_eq!(parse_proc_stat("cpu 10 0 5 85\n"), Some((15, 100)));
+        assert_eq!(parse_proc_stat("cpu0 10 0 5 85\n"), None);
+        assert_eq!(parse_proc_stat("cpu 10 x 5 85\n"), None);
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:       16384000 kB\n\
+                       MemFree:         1024000 kB\n\
+                       MemAvailable:   10240000 kB\n\
+                       Buffers:          204800 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(MemoryInfo {
+            used_bytes: 6_144_000 * 1024,
+            total_bytes: 16_384_000 * 1024,
+        }));
+        assert_eq!(parse_meminfo("MemTotal: 1024 kB\n"), None);
+    }
+
+    fn supply(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let dir = root.join("sys/class/power_supply").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attr, value) in attrs {
+            fs::write(dir.join(attr), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_power_supply() {
+        let root = env::temp_dir().join(format!("sketchybartender-linux-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let mut provider = PowerSupply { root: root.clone() };
+        assert_eq!(provider.read(), Ok(None));
+        supply(&root, "AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(provider.read(), Ok(None));
+        fs::remove_dir_all(&root).unwrap();
+
+        supply(&root, "BAT0", &[("type", "Battery"), ("capacity", "42"), ("status", "Discharging")]);
+        let battery = provider.read().unwrap().unwrap();
+        assert_eq!((battery.percentage, battery.state, battery.supply), (42, ChargeState::Discharging, Supply::Battery));
+        assert_eq!(battery.minutes_remaining, None);
+
+        // 30 Wh left at 10 W
+        supply(&root, "BAT0", &[("energy_now", "30000000"), ("energy_full", "50000000"), ("power_now", "10000000")]);
+        assert_eq!(provider.read().unwrap().unwrap().minutes_remaining, Some(180));
+        supply(&root, "BAT0", &[("status", "Charging")]);
+        assert_eq!(provider.read().unwrap().unwrap().minutes_remaining, Some(120));
+
+        // "Not charging" at a charge threshold still means plugged in
+        supply(&root, "BAT0", &[("status", "Not charging")]);
+        let battery = provider.read().unwrap().unwrap();
+        assert_eq!((battery.state, battery.supply), (ChargeState::NotCharging, Supply::Ac));
+        assert_eq!(battery.minutes_remaining, None);
+
+        // The adapter wins over the battery status
+        supply(&root, "AC", &[("type", "Mains"), ("online", "0")]);
+        supply(&root, "Apple-Magic-Mouse", &[("type", "Battery"), ("capacity", "5"), ("scope", "Device")]);
+        let battery = provider.read().unwrap().unwrap();
+        assert_eq!((battery.percentage, battery.supply), (42, Supply::Battery));
+        supply(&root, "AC", &[("online", "1")]);
+        assert_eq!(provider.read().unwrap().unwrap().supply, Supply::Ac);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
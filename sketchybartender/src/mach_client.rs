@@ -8,7 +8,9 @@ use mach2::task_special_ports::*;
 use mach2::traps::*;
 use std::ffi::CString;
 use std::os::raw::c_void;
-use std::sync::Mutex;
+use std::thread;
+
+use crate::reconnect::{Connection, Delivery, SendError, Transport, DEFAULT_BACKOFF};
 
 const SKETCHYBAR_MACH_SERVICE: &str = "git.felix.sketchybar";
 
@@ -59,8 +61,9 @@ struct MachBuffer {
     trailer: mach_msg_trailer_t,
 }
 
-/// Global mach port cache (lazy initialization)
-static MACH_PORT: Mutex<Option<mach_port_t>> = Mutex::new(None);
+/// Global mach port cache, re-established when sketchybar restarts
+static CONNECTION: Connection<MachTransport> =
+    Connection::new(MachTransport, DEFAULT_BACKOFF, thread::sleep);
 
 /// Get the bootstrap port for sketchybar
 fn get_sketchybar_port() -> Result<mach_port_t, String> {
@@ -86,52 +89,16 @@ fn get_sketchybar_port() -> Result<mach_port_t, String> {
     }
 }
 
-/// Format a command message for sketchybar (converting spaces outside quotes to null bytes)
-/// This matches the C implementation's formatting logic
-fn format_message(message: &str) -> Vec<u8> {
-    let message_bytes = message.as_bytes();
-    let mut formatted = Vec::with_capacity(message.len() + 1);
-    let mut quote = 0u8; // 0 means no quote, otherwise it's the quote character
-
-    for &byte in message_bytes {
-        // Handle quote characters
-        if byte == b'"' || byte == b'\'' {
-            if quote == byte {
-                // Closing quote
-                quote = 0;
-            } else if quote == 0 {
-                // Opening quote
-                quote = byte;
-            } else {
-                // Inside a different quote type, keep it
-                formatted.push(byte);
-            }
-            // Don't push the quote character itself
-            continue;
-        }
-
-        // Convert spaces to null bytes if not inside quotes
-        if byte == b' ' && quote == 0 {
-            formatted.push(0);
-        } else {
-            formatted.push(byte);
-        }
-    }
-
-    // Remove trailing null if present and previous byte is also null
-    if formatted.len() > 1
-        && formatted[formatted.len() - 1] == 0
-        && formatted[formatted.len() - 2] == 0
-    {
-        formatted.pop();
-    }
-
-    formatted.push(0); // Final null terminator
-    formatted
+/// Whether a failed `mach_msg` send means the destination port is gone
+fn is_dead_port(kr: mach_msg_return_t) -> bool {
+    matches!(
+        kr,
+        MACH_SEND_INVALID_DEST | MACH_SEND_INVALID_RIGHT | MACH_SEND_INVALID_HEADER
+    )
 }
 
 /// Send a message to sketchybar via mach port and optionally receive a response
-fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, String> {
+fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, SendError> {
     unsafe {
         let task = mach_task_self();
 
@@ -139,7 +106,7 @@ fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, Str
         let mut response_port: mach_port_t = 0;
         let kr = mach_port_allocate(task, MACH_PORT_RIGHT_RECEIVE, &mut response_port);
         if kr != KERN_SUCCESS {
-            return Err(format!("Failed to allocate response port: {}", kr));
+            return Err(SendError::Other(format!("Failed to allocate response port: {}", kr)));
         }
 
         // Insert send right
@@ -151,7 +118,7 @@ fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, Str
         );
         if kr != KERN_SUCCESS {
             mach_port_mod_refs(task, response_port, MACH_PORT_RIGHT_RECEIVE, -1);
-            return Err(format!("Failed to insert right: {}", kr));
+            return Err(SendError::Other(format!("Failed to insert right: {}", kr)));
         }
 
         // Prepare the message - matching C implementation exactly
@@ -189,7 +156,12 @@ fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, Str
         if kr != KERN_SUCCESS {
             mach_port_mod_refs(task, response_port, MACH_PORT_RIGHT_RECEIVE, -1);
             mach_port_deallocate(task, response_port);
-            return Err(format!("Failed to send message: {} (0x{:x})", kr, kr));
+            let message = format!("Failed to send message: {} (0x{:x})", kr, kr);
+            return Err(if is_dead_port(kr) {
+                SendError::DeadPort(message)
+            } else {
+                SendError::Other(message)
+            });
         }
 
         // Receive the response with timeout
@@ -210,10 +182,10 @@ fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, Str
         mach_port_deallocate(task, response_port);
 
         if kr != KERN_SUCCESS {
-            if kr == MACH_RCV_TIMED_OUT as i32 {
+            if kr == MACH_RCV_TIMED_OUT {
                 return Ok(None); // Timeout is okay, sketchybar might not respond
             }
-            return Err(format!("Failed to receive response: {}", kr));
+            return Err(SendError::Other(format!("Failed to receive response: {}", kr)));
         }
 
         // Extract the response if available
@@ -240,54 +212,29 @@ fn send_message(port: mach_port_t, message: &[u8]) -> Result<Option<String>, Str
     }
 }
 
-/// Send a command to sketchybar
-pub fn sketchybar(command: &str) -> Result<Option<String>, String> {
-    // Get or initialize the mach port
-    let port = {
-        let mut cached_port = MACH_PORT.lock().unwrap();
-        if let Some(port) = *cached_port {
-            port
-        } else {
-            let port = get_sketchybar_port()?;
-            *cached_port = Some(port);
-            port
-        }
-    };
-
-    // Format the message
-    let formatted = format_message(command);
-
-    // Send the message
-    send_message(port, &formatted)
-}
-
-/// Reset the cached mach port (useful if sketchybar restarts)
-#[allow(dead_code)]
-pub fn reset_port() {
-    let mut cached_port = MACH_PORT.lock().unwrap();
-    *cached_port = None;
-}
+/// Mach implementation of the reconnect `Transport`
+struct MachTransport;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Transport for MachTransport {
+    type Port = mach_port_t;
 
-    #[test]
-    fn test_format_message() {
-        let msg = format_message("--set item label=\"hello world\"");
-        // Should convert to: --set\0item\0label=hello world\0
-        assert_eq!(msg, b"--set\0item\0label=hello world\0");
+    fn lookup(&self) -> Result<mach_port_t, String> {
+        get_sketchybar_port()
     }
 
-    #[test]
-    fn test_format_message_single_quotes() {
-        let msg = format_message("--set item label='hello world'");
-        assert_eq!(msg, b"--set\0item\0label=hello world\0");
+    fn send(&self, port: mach_port_t, message: &[u8]) -> Result<(), SendError> {
+        send_message(port, message).map(|_| ())
     }
 
-    #[test]
-    fn test_format_message_no_quotes() {
-        let msg = format_message("--set item icon=X");
-        assert_eq!(msg, b"--set\0item\0icon=X\0");
+    fn release(&self, port: mach_port_t) {
+        unsafe {
+            mach_port_deallocate(mach_task_self(), port);
+        }
     }
 }
+
+/// Send an encoded command (see `sink::encode_args`) to sketchybar,
+/// reconnecting if sketchybar was restarted
+pub fn sketchybar(message: &[u8]) -> Result<Delivery, String> {
+    CONNECTION.send(message)
+}
@@ -0,0 +1,143 @@
+//! macOS backends: the kernel's mach statistics and `pmset`
+
+use std::process::Command;
+
+use crate::providers::{BatteryInfo, CpuTicks, MemoryInfo};
+use crate::pmset;
+use crate::registry::Provider;
+
+/// CPU ticks from `host_statistics`.
+///
+/// This is the same `HOST_CPU_LOAD_INFO` data Activity Monitor derives its CPU
+/// figures from. A single call costs microseconds and — unlike `top` — spawns
+/// no process and runs no sampling pass, so it doesn't pollute its own
+/// measurement window (which is what made `top` over-report `sys`).
+pub struct MachCpu;
+
+impl Provider for MachCpu {
+    type Snapshot = CpuTicks;
+
+    fn backend(&self) -> &'static str {
+        "host_statistics"
+    }
+
+    fn read(&mut self) -> Result<CpuTicks, String> {
+        use std::mem::MaybeUninit;
+
+        let mut info = MaybeUninit::<libc::host_cpu_load_info>::uninit();
+        let mut count = libc::HOST_CPU_LOAD_INFO_COUNT;
+        let kr = unsafe {
+            libc::host_statistics(
+                mach2::mach_init::mach_host_self(),
+                libc::HOST_CPU_LOAD_INFO,
+                info.as_mut_ptr() as libc::host_info_t,
+                &mut count,
+            )
+        };
+        if kr != libc::KERN_SUCCESS {
+            return Err(format!("host_statistics failed ({})", kr));
+        }
+        let info = unsafe { info.assume_init() };
+        let ticks = &info.cpu_ticks;
+        let user = ticks[libc::CPU_STATE_USER as usize] as u64;
+        let sys = ticks[libc::CPU_STATE_SYSTEM as usize] as u64;
+        let nice = ticks[libc::CPU_STATE_NICE as usize] as u64;
+        let idle = ticks[libc::CPU_STATE_IDLE as usize] as u64;
+        let busy = user + sys + nice;
+        Ok((busy, busy + idle))
+    }
+}
+
+/// Memory use matching Activity Monitor's "Memory Used", read from the
+/// kernel's VM statistics via `host_statistics64` — no `vm_stat`/`sysctl`
+/// subprocess.
+///
+/// macOS keeps almost nothing truly free — inactive, speculative and
+/// purgeable pages are all reclaimable and are NOT counted as "used" by
+/// Activity Monitor. "Memory Used" = App Memory + Wired + Compressed:
+///   used = (active + wired + compressor - purgeable) * page_size
+pub struct MachMemory;
+
+impl Provider for MachMemory {
+    type Snapshot = MemoryInfo;
+
+    fn backend(&self) -> &'static str {
+        "host_statistics64"
+    }
+
+    fn read(&mut self) -> Result<MemoryInfo, String> {
+        use std::mem::MaybeUninit;
+
+        let total_bytes = total_memory_bytes();
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        let page_size = if page_size > 0 { page_size as u64 } else { 4096 };
+
+        let mut info = MaybeUninit::<libc::vm_statistics64>::uninit();
+        let mut count = libc::HOST_VM_INFO64_COUNT;
+        let kr = unsafe {
+            libc::host_statistics64(
+                mach2::mach_init::mach_host_self(),
+                libc::HOST_VM_INFO64,
+                info.as_mut_ptr() as libc::host_info64_t,
+                &mut count,
+            )
+        };
+        if kr != libc::KERN_SUCCESS {
+            return Err(format!("host_statistics64 failed ({})", kr));
+        }
+        let vm = unsafe { info.assume_init() };
+
+        let used_pages = (vm.active_count as u64
+            + vm.wire_count as u64
+            + vm.compressor_page_count as u64)
+            .saturating_sub(vm.purgeable_count as u64);
+
+        Ok(MemoryInfo {
+            used_bytes: used_pages * page_size,
+            total_bytes,
+        })
+    }
+}
+
+/// Total physical memory in bytes, from `sysctlbyname("hw.memsize")`.
+/// This value is invariant for the life of the process.
+fn total_memory_bytes() -> u64 {
+    let mut value: u64 = 0;
+    let mut size = std::mem::size_of::<u64>();
+    let name = b"hw.memsize\0";
+    let rc = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr() as *const libc::c_char,
+            &mut value as *mut u64 as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if rc == 0 {
+        value
+    } else {
+        0
+    }
+}
+
+/// Battery charge and power source from `pmset -g batt`
+pub struct Pmset;
+
+impl Provider for Pmset {
+    type Snapshot = Option<BatteryInfo>;
+
+    fn backend(&self) -> &'static str {
+        "pmset"
+    }
+
+    fn read(&mut self) -> Result<Option<BatteryInfo>, String> {
+        let output = Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+            .map_err(|e| format!("Failed to run pmset: {}", e))?;
+
+        pmset::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+}
@@ -1,104 +1,377 @@
 mod aerospace;
 mod aerospace_focus;
+mod alerts;
+mod batch;
+mod clock;
+mod coalesce;
 mod config;
+mod config_format;
 mod daemon;
 mod handlers;
 mod icon_map;
+mod icons;
+mod layers;
+#[cfg(any(not(target_os = "macos"), test))]
+mod linux;
+#[cfg(target_os = "macos")]
 mod mach_client;
+#[cfg(target_os = "macos")]
+mod macos;
+mod migration;
+mod ordering;
+#[cfg(any(target_os = "macos", test))]
+mod pmset;
+mod protocol;
 mod providers;
+mod reconnect;
+mod registry;
+mod reload;
+mod render;
+mod scheduler;
+mod sink;
 
+use std::env;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
 
-use chrono::{Local, Timelike};
 use handlers::DaemonState;
+use scheduler::Repeat;
+use sink::SinkKind;
 
-fn main() {
-    // Load configuration
-    let config = config::Config::load();
+const USAGE: &str = "Usage: sketchybartender [--sink mach|cli|stdout] [--dry-run]
+       sketchybartender --check-config [FILE]
+       sketchybartender --migrate-config [FILE]
+       sketchybartender --print-config";
 
-    // Shared state
-    let state = Arc::new(Mutex::new(DaemonState::new(config.clone())));
-
-    // Spawn brew refresh early (before delay) since it takes the longest
-    let brew_interval = config.brew_interval;
-    thread::spawn(move || {
-        // Initial refresh
-        handlers::handle_brew_refresh();
-        
-        loop {
-            thread::sleep(Duration::from_secs(brew_interval));
-            handlers::handle_brew_refresh();
-        }
-    });
+/// Command line options for the daemon
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    /// Overrides the sink from the config file
+    sink: Option<SinkKind>,
+    /// Check this config file and exit instead of starting the daemon
+    check_config: Option<PathBuf>,
+    /// Upgrade this config file to the current version and exit
+    migrate_config: Option<PathBuf>,
+    /// Print the merged config with where each value came from and exit
+    print_config: bool,
+}
 
-    // Wait for sketchybar to be ready
-    thread::sleep(Duration::from_millis(200));
+fn parse_options(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check-config" => {
+                let path = args.next_if(|a| !a.starts_with("--")).map(PathBuf::from);
+                options.check_config = Some(path.unwrap_or_else(config::Config::get_config_path));
+            }
+            "--migrate-config" => {
+                let path = args.next_if(|a| !a.starts_with("--")).map(PathBuf::from);
+                options.migrate_config = Some(path.unwrap_or_else(config::Config::get_config_path));
+            }
+            "--print-config" => options.print_config = true,
+            "--sink" => {
+                let kind = args.next().ok_or("--sink requires a value")?;
+                options.sink = Some(kind.parse()?);
+            }
+            "--dry-run" => options.sink = Some(SinkKind::Stdout),
+            _ => return Err(format!("Unknown argument '{}'", arg)),
+        }
+    }
+    Ok(options)
+}
 
-    // Spawn refresh thread for workspace (event-driven, but needs initial refresh)
-    let workspace_state = Arc::clone(&state);
-    thread::spawn(move || {
-        // Initial refresh
-        handlers::handle_workspace_refresh(&workspace_state);
-    });
+/// Print every problem in the config file at `path`; returns the exit code
+fn check_config(path: &Path) -> i32 {
+    let check = match config::Config::check_file(path) {
+        Ok(check) => check,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    for error in &check.errors {
+        println!("error: {}", error);
+    }
+    for warning in &check.warnings {
+        println!("warning: {}", warning);
+    }
+    if check.from_version < migration::CURRENT_VERSION {
+        println!(
+            "note: written for version {}, run --migrate-config to upgrade it to {}",
+            check.from_version,
+            migration::CURRENT_VERSION
+        );
+    }
+    if check.errors.is_empty() {
+        println!("{}: ok", path.display());
+        0
+    } else {
+        1
+    }
+}
 
-    // Spawn timer threads for periodic updates using configured intervals
-    // Smart clock refresh: poll every 2 seconds until minute changes, then switch to 1-minute intervals
-    thread::spawn(move || {
-        // Initial refresh
-        handlers::handle_clock_refresh();
-        
-        // Track the current minute
-        let last_minute = Local::now().minute();
-        
-        // Poll every 2 seconds until we detect a minute change
-        loop {
-            thread::sleep(Duration::from_secs(2));
-            let current_minute = Local::now().minute();
-            
-            if current_minute != last_minute {
-                // Minute changed! Wait 0.5 seconds and switch to 1-minute intervals
-                thread::sleep(Duration::from_millis(500));
-                handlers::handle_clock_refresh();
-                break;
+/// Upgrade the config file at `path` in place; returns the exit code
+fn migrate_config(path: &Path) -> i32 {
+    match config::Config::migrate_file(path) {
+        Ok(None) => {
+            println!("{}: already version {}", path.display(), migration::CURRENT_VERSION);
+            0
+        }
+        Ok(Some((from, backup, notes))) => {
+            println!(
+                "{}: migrated from version {} to {} (original kept at {})",
+                path.display(),
+                from,
+                migration::CURRENT_VERSION,
+                backup.display()
+            );
+            for note in notes {
+                println!("  {}", note);
             }
-            
-            handlers::handle_clock_refresh();
+            0
         }
-        
-        // Now use 1-minute intervals, synchronized to the minute boundary
-        loop {
-            thread::sleep(Duration::from_secs(60));
-            handlers::handle_clock_refresh();
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            1
         }
+    }
+}
+
+/// Print the effective config, one value per line with the layer that set
+/// it; returns the exit code
+fn print_config() -> i32 {
+    let (check, merged) = config::Config::check_layers();
+    for error in &check.errors {
+        eprintln!("error: {}", error);
+    }
+    for warning in &check.warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let config = check
+        .config
+        .map(|c| c.with_defaults_for(&check.errors))
+        .unwrap_or_default();
+    let effective = serde_json::to_value(&config).unwrap_or_default();
+    let lines: Vec<(String, layers::Source)> = merged
+        .describe(&effective)
+        .into_iter()
+        .map(|(path, value, source)| (format!("{} = {}", path, value), source))
+        .collect();
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, source) in lines {
+        println!("{:width$}  # {}", line, source, width = width);
+    }
+
+    if check.errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// Register every timed refresh with the daemon's scheduler, which is
+/// returned ready to start
+fn add_jobs(state: &Arc<Mutex<DaemonState>>) -> Arc<scheduler::Scheduler> {
+    let (scheduler, registry, system_delay) = {
+        let s = state.lock().expect("fresh state lock");
+        (
+            Arc::clone(&s.scheduler),
+            Arc::clone(&s.registry),
+            Duration::from_secs(s.config.intervals.system),
+        )
+    };
+
+    // Brew goes first since it takes the longest
+    scheduler.add("brew", Repeat::Every(|c| Duration::from_secs(c.intervals.brew)), Duration::ZERO, |_| {
+        handlers::handle_brew_refresh()
     });
 
-    let battery_interval = config.battery_interval;
-    let battery_config = config.clone();
-    thread::spawn(move || {
-        // Initial refresh
-        handlers::handle_battery_refresh(None, &battery_config);
+    // Everything else waits for sketchybar to be ready
+    let startup = Duration::from_millis(200);
 
-        loop {
-            thread::sleep(Duration::from_secs(battery_interval));
-            handlers::handle_battery_refresh(None, &battery_config);
+    // Workspaces are event-driven, but need an initial refresh
+    let workspace_state = Arc::clone(state);
+    scheduler.add("workspaces", Repeat::Once, startup, move |_| {
+        // Start from a clean slate, then add items for the current workspaces
+        if let Err(e) = handlers::remove_workspace_items() {
+            eprintln!("{}", e);
         }
+        handlers::refresh_workspaces(&workspace_state)
     });
 
-    let system_interval = config.system_interval;
-    thread::spawn(move || {
-        // Bootstrap the CPU tick counters so the first displayed value (one
-        // interval from now) is a real reading rather than 0%.
-        let mut prev_cpu = providers::read_cpu_ticks();
-        handlers::handle_system_refresh(&mut prev_cpu);
+    // The clock ticks on the minute (or second), and at least every
+    // `intervals.clock` seconds to catch up after sleep
+    let clock_tick = |c: &config::Config, wall| {
+        let seconds = providers::format_needs_seconds(&c.clock.format)
+            || (!c.clock.zones.is_empty() && providers::format_needs_seconds(&c.clock.zone_format));
+        clock::until_next_tick(wall, seconds, Duration::from_secs(c.intervals.clock))
+    };
+    scheduler.add("clock", Repeat::Aligned(clock_tick), startup, |config| {
+        handlers::handle_clock_refresh(config)
+    });
 
-        loop {
-            thread::sleep(Duration::from_secs(system_interval));
-            handlers::handle_system_refresh(&mut prev_cpu);
-        }
+    // Also picks the interval profile for the power source
+    let battery_state = Arc::clone(state);
+    scheduler.add("battery", Repeat::Every(|c| Duration::from_secs(c.intervals.battery)), startup, move |_| {
+        handlers::handle_battery_refresh(None, &battery_state)
+    });
+
+    // Bootstrap the CPU tick counters so the first displayed value (one
+    // interval from now) is a real reading rather than 0%.
+    let mut prev_cpu = registry.cpu.read().ok();
+    scheduler.add("system", Repeat::Every(|c| Duration::from_secs(c.intervals.system)), system_delay, move |_| {
+        handlers::handle_system_refresh(&registry, &mut prev_cpu)
     });
 
+    scheduler.add("teams", Repeat::Every(|c| Duration::from_secs(c.intervals.teams)), startup, |_| {
+        handlers::handle_teams_refresh()
+    });
+
+    // Pick up edits to the config file without a restart
+    scheduler.add(
+        "config-watch",
+        Repeat::Every(|_| reload::WATCH_INTERVAL),
+        reload::WATCH_INTERVAL,
+        reload::config_watcher(Arc::clone(state)),
+    );
+
+    scheduler
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = &options.check_config {
+        std::process::exit(check_config(path));
+    }
+    if let Some(path) = &options.migrate_config {
+        std::process::exit(migrate_config(path));
+    }
+    if options.print_config {
+        std::process::exit(print_config());
+    }
+
+    // Load configuration
+    let config = config::Config::load();
+
+    // Pick where bar updates go; the command line wins over the config file
+    sink::install(options.sink.unwrap_or(config.sink).build());
+
+    // Shared state
+    let state = Arc::new(Mutex::new(DaemonState::new(config)));
+
+    let scheduler = add_jobs(&state);
+
+    scheduler.start();
+
     // Start the daemon socket listener
     daemon::start_daemon(state);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+    use std::time::Instant;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(parse_options(args(&[])).unwrap(), Options::default());
+        assert_eq!(
+            parse_options(args(&["--sink", "cli"])).unwrap().sink,
+            Some(SinkKind::Cli)
+        );
+        assert_eq!(
+            parse_options(args(&["--dry-run"])).unwrap().sink,
+            Some(SinkKind::Stdout)
+        );
+        assert_eq!(
+            parse_options(args(&["--check-config", "/tmp/c.json"])).unwrap().check_config,
+            Some(PathBuf::from("/tmp/c.json"))
+        );
+        assert_eq!(
+            parse_options(args(&["--check-config", "--dry-run"])).unwrap().check_config,
+            Some(config::Config::get_config_path())
+        );
+        assert!(parse_options(args(&["--print-config"])).unwrap().print_config);
+        assert!(parse_options(args(&["--sink"])).is_err());
+        assert!(parse_options(args(&["--bogus"])).is_err());
+    }
+
+    /// Stand-ins for the commands the event-driven items query
+    const FAKE_COMMANDS: &[(&str, &str)] = &[
+        ("aerospace", r#"case "$1 $2" in
+  "list-workspaces --all") printf '1\n2\n' ;;
+  "list-windows --focused") echo Safari ;;
+  "list-windows --all") echo '[{"app-name": "Safari", "workspace": "1", "workspace-is-focused": true, "workspace-is-visible": true, "monitor-appkit-nsscreen-screens-id": 1}]' ;;
+esac"#),
+        ("osascript", r#"case "$2" in *muted*) echo false ;; *) echo 40 ;; esac"#),
+        ("brew", "exit 0"),
+    ];
+
+    #[test]
+    fn test_wake_sets_every_item() {
+        let root = env::temp_dir().join(format!("sketchybartender-wake-{}", std::process::id()));
+        let bin = root.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        for (name, script) in FAKE_COMMANDS {
+            let path = bin.join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path = env::var("PATH").unwrap_or_default();
+        env::set_var("PATH", format!("{}:{}", bin.display(), path));
+        let battery = root.join("sys/class/power_supply/BAT0");
+        fs::create_dir_all(&battery).unwrap();
+        for (attr, value) in [("type", "Battery"), ("capacity", "80"), ("status", "Discharging")] {
+            fs::write(battery.join(attr), value).unwrap();
+        }
+
+        let sink = Arc::new(sink::RecordingSink::new());
+        sink::install(sink.clone());
+        let mut daemon = DaemonState::new(config::Config::default());
+        daemon.registry = Arc::new(registry::Registry::linux(&root));
+        let state = Arc::new(Mutex::new(daemon));
+        let scheduler = add_jobs(&state);
+
+        handlers::handle_sleep(&state).unwrap();
+        scheduler.run_due(|run| run());
+        assert!(sink.args().is_empty());
+
+        handlers::handle_system_wake(&state).unwrap();
+        scheduler.run_due(|run| run());
+        // Workspaces are refreshed once the coalescing window has passed
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !sink.args().contains(&"workspace.2".to_string()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let set: Vec<String> = sink
+            .batches()
+            .iter()
+            .flat_map(|batch| batch.windows(2).filter(|w| w[0] == "--set").map(|w| w[1].clone()).collect::<Vec<_>>())
+            .collect();
+        for item in ["brew", "clock", "battery", "cpu", "ram", "teams", "volume", "front_app", "workspace.1", "workspace.2"] {
+            assert!(set.contains(&item.to_string()), "{} not set: {:?}", item, set);
+        }
+
+        env::set_var("PATH", path);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
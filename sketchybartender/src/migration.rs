@@ -0,0 +1,194 @@
+//! Config schema versions and migrations
+//!
+//! Config files carry a `version`. Older files are upgraded in memory, one
+//! version at a time, before they are deserialized, so keys that were renamed
+//! or moved keep working. `--migrate-config` writes the upgraded file back.
+//!
+//! Version history:
+//! - 1: the original flat layout (no `version` key)
+//! - 2: intervals moved into an `intervals` section; the unused
+//!   `workspace_bg_color` is gone
+
+use serde_json::{Map, Value};
+
+/// Version written by this build
+pub const CURRENT_VERSION: u32 = 2;
+
+/// A config upgraded to `CURRENT_VERSION`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migrated {
+    pub value: Value,
+    /// Version the file was written for
+    pub from_version: u32,
+    /// What was changed, one line per change
+    pub notes: Vec<String>,
+}
+
+type Step = fn(&mut Map<String, Value>, &mut Vec<String>);
+
+/// `STEPS[i]` upgrades version `i + 1` to `i + 2`
+const STEPS: &[Step] = &[v1_to_v2];
+
+/// Version a config was written for; files without a `version` predate it
+pub fn version_of(value: &Value) -> Result<u32, String> {
+    match value.get("version") {
+        None => Ok(1),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| format!("expected a positive integer, found {}", v)),
+    }
+}
+
+/// Upgrade `value` to `CURRENT_VERSION`
+pub fn migrate(mut value: Value) -> Result<Migrated, String> {
+    let from_version = version_of(&value)?;
+    if from_version > CURRENT_VERSION {
+        return Err(format!(
+            "config version {} is newer than this sketchybartender supports ({})",
+            from_version, CURRENT_VERSION
+        ));
+    }
+
+    let mut notes = Vec::new();
+    if let Some(map) = value.as_object_mut() {
+        for (i, step) in STEPS.iter().enumerate().skip(from_version as usize - 1) {
+            step(map, &mut notes);
+            map.insert("version".to_string(), Value::from(i as u32 + 2));
+        }
+    }
+    Ok(Migrated {
+        value,
+        from_version,
+        notes,
+    })
+}
+
+/// Version 1 → 2: `*_interval` keys move into `intervals`, and
+/// `workspace_bg_color` is dropped (it never had any effect; with the
+/// gradient off the background is `workspace_gradient_start`)
+fn v1_to_v2(map: &mut Map<String, Value>, notes: &mut Vec<String>) {
+    let legacy = [
+        ("clock_interval", "clock"),
+        ("battery_interval", "battery"),
+        ("brew_interval", "brew"),
+        ("teams_interval", "teams"),
+        ("system_interval", "system"),
+    ];
+    for (old, new) in legacy {
+        let Some(secs) = map.remove(old) else {
+            continue;
+        };
+        let intervals = map
+            .entry("intervals")
+            .or_insert_with(|| Value::Object(Map::new()));
+        match intervals.as_object_mut() {
+            Some(intervals) if !intervals.contains_key(new) => {
+                intervals.insert(new.to_string(), secs);
+                notes.push(format!("moved `{}` to `intervals.{}`", old, new));
+            }
+            _ => notes.push(format!("dropped `{}`, `intervals.{}` takes precedence", old, new)),
+        }
+    }
+
+    if let Some(color) = map.remove("workspace_bg_color") {
+        let solid = map.get("workspace_gradient") == Some(&Value::Bool(false));
+        if solid && !map.contains_key("workspace_gradient_start") {
+            map.insert("workspace_gradient_start".to_string(), color);
+            notes.push("moved `workspace_bg_color` to `workspace_gradient_start`".to_string());
+        } else {
+            notes.push("dropped unused `workspace_bg_color`".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::config_format::Format;
+    use serde_json::json;
+
+    const V1: &str = include_str!("../fixtures/config/v1.json");
+    const V1_SOLID_BACKGROUND: &str = include_str!("../fixtures/config/v1-solid-background.json");
+    const V2: &str = include_str!("../fixtures/config/v2.json");
+
+    fn migrated(fixture: &str) -> Migrated {
+        migrate(serde_json::from_str(fixture).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_v1_fixture() {
+        let m = migrated(V1);
+        assert_eq!(m.from_version, 1);
+        assert_eq!(m.value["version"], 2);
+        assert_eq!(m.value["intervals"], json!({
+            "clock": 15, "battery": 120, "brew": 3600, "teams": 30, "system": 5,
+        }));
+        assert!(m.value.get("clock_interval").is_none());
+        assert!(m.value.get("workspace_bg_color").is_none());
+        assert_eq!(m.notes.len(), 6, "{:?}", m.notes);
+
+        let check = Config::check_str(V1, Format::Json);
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        assert!(check.warnings.is_empty(), "{:?}", check.warnings);
+        let config = check.config.unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.intervals.brew, 3600);
+        assert_eq!(config.border_active_color, "0xffa9b665");
+    }
+
+    #[test]
+    fn test_v1_solid_background_fixture() {
+        let m = migrated(V1_SOLID_BACKGROUND);
+        assert_eq!(m.value["workspace_gradient_start"], "0xff45475a");
+        assert_eq!(m.value["intervals"], json!({"clock": 30, "system": 2}));
+
+        let config = Config::check_str(V1_SOLID_BACKGROUND, Format::Json).config.unwrap();
+        assert_eq!(config.intervals.clock, 30);
+        assert_eq!(config.intervals.battery, 120);
+        assert!(!config.workspace_gradient);
+        assert_eq!(config.workspace_gradient_start, "0xff45475a");
+    }
+
+    #[test]
+    fn test_v2_fixture_is_current() {
+        let m = migrated(V2);
+        assert_eq!(m.from_version, 2);
+        assert!(m.notes.is_empty());
+        assert_eq!(m.value, serde_json::from_str::<Value>(V2).unwrap());
+
+        let check = Config::check_str(V2, Format::Json);
+        assert!(check.errors.is_empty(), "{:?}", check.errors);
+        assert!(check.warnings.is_empty(), "{:?}", check.warnings);
+    }
+
+    #[test]
+    fn test_example_config_is_current() {
+        let example = include_str!("../../sketchybartender.default.json");
+        assert_eq!(migrated(example).from_version, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_intervals_section_wins_over_legacy_keys() {
+        let m = migrate(json!({"clock_interval": 30, "intervals": {"clock": 60}})).unwrap();
+        assert_eq!(m.value["intervals"]["clock"], 60);
+        assert!(m.value.get("clock_interval").is_none());
+    }
+
+    #[test]
+    fn test_bad_versions() {
+        assert!(migrate(json!({"version": 3})).unwrap_err().contains("newer"));
+        assert!(migrate(json!({"version": 0})).is_err());
+        assert!(migrate(json!({"version": "2"})).is_err());
+    }
+
+    #[test]
+    fn test_migrating_twice_changes_nothing() {
+        let once = migrated(V1);
+        let twice = migrate(once.value.clone()).unwrap();
+        assert_eq!(twice.value, once.value);
+        assert!(twice.notes.is_empty());
+    }
+}
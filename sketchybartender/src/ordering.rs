@@ -0,0 +1,125 @@
+//! Workspace ordering
+//!
+//! Workspace names are compared "naturally" (runs of digits as numbers, so
+//! "2" sorts before "10"), with an optional explicit order from the config
+//! taking precedence. The bar order and the gradient position both follow it.
+
+use std::cmp::Ordering;
+
+/// Split `s` into alternating runs of ASCII digits and everything else
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let is_digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// Compare two digit runs by numeric value without parsing (no overflow)
+fn cmp_numeric(a: &str, b: &str) -> Ordering {
+    let a_trimmed = a.trim_start_matches('0');
+    let b_trimmed = b.trim_start_matches('0');
+    a_trimmed
+        .len()
+        .cmp(&b_trimmed.len())
+        .then_with(|| a_trimmed.cmp(b_trimmed))
+        // "1" before "01" so equal values still have a stable order
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+/// Natural string comparison: digit runs compare as numbers, the rest as text
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    loop {
+        let ordering = match (a_chunks.next(), b_chunks.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let x_digits = x.starts_with(|c: char| c.is_ascii_digit());
+                let y_digits = y.starts_with(|c: char| c.is_ascii_digit());
+                if x_digits && y_digits {
+                    cmp_numeric(x, y)
+                } else {
+                    x.cmp(y)
+                }
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Sort workspace IDs for the bar: IDs listed in `order` come first, in that
+/// order; everything else follows in natural order
+pub fn sort_workspaces<'a>(ids: impl IntoIterator<Item = &'a String>, order: &[String]) -> Vec<&'a String> {
+    let rank = |id: &str| order.iter().position(|o| o == id).unwrap_or(usize::MAX);
+    let mut sorted: Vec<&String> = ids.into_iter().collect();
+    sorted.sort_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| natural_cmp(a, b)));
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn sorted(ids: &[&str], order: &[&str]) -> Vec<String> {
+        let ids = strings(ids);
+        sort_workspaces(&ids, &strings(order)).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_numbers_sort_by_value() {
+        assert_eq!(sorted(&["10", "2", "1", "9"], &[]), strings(&["1", "2", "9", "10"]));
+    }
+
+    #[test]
+    fn test_numbers_before_letters() {
+        assert_eq!(sorted(&["b", "10", "a", "3"], &[]), strings(&["3", "10", "a", "b"]));
+    }
+
+    #[test]
+    fn test_mixed_names() {
+        assert_eq!(
+            sorted(&["ws10", "ws2", "ws1a", "ws1"], &[]),
+            strings(&["ws1", "ws1a", "ws2", "ws10"])
+        );
+        assert_eq!(natural_cmp("01", "1"), Ordering::Greater);
+        assert_eq!(natural_cmp("007", "7"), Ordering::Greater);
+        assert_eq!(natural_cmp("007", "8"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_explicit_order_comes_first() {
+        assert_eq!(
+            sorted(&["1", "2", "10", "m", "c"], &["m", "10", "missing"]),
+            strings(&["m", "10", "1", "2", "c"])
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_numbers_compare_by_value(a in 0u128..u128::MAX, b in 0u128..u128::MAX) {
+            prop_assert_eq!(natural_cmp(&a.to_string(), &b.to_string()), a.cmp(&b));
+        }
+
+        #[test]
+        fn prop_antisymmetric(a in "[a-c0-9]{0,6}", b in "[a-c0-9]{0,6}") {
+            prop_assert_eq!(natural_cmp(&a, &b), natural_cmp(&b, &a).reverse());
+            prop_assert_eq!(natural_cmp(&a, &b) == Ordering::Equal, a == b);
+        }
+    }
+}
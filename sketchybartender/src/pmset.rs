@@ -0,0 +1,116 @@
+//! Parser for `pmset -g batt`
+//!
+//! The output names the power source on the first line, then has one line per
+//! battery (the internal one, and any UPS):
+//!
+//! ```text
+//! Now drawing from 'Battery Power'
+//!  -InternalBattery-0 (id=4653155)<TAB>85%; discharging; 5:32 remaining present: true
+//! ```
+//!
+//! The remaining time can also be `(no estimate)` or, on AC, `not charging`.
+//! Older releases leave out the id and `present:`, and a Mac without a
+//! battery or UPS has no battery lines at all.
+
+use crate::providers::{BatteryInfo, ChargeState, Supply};
+
+/// The battery's state; none if the machine has no battery
+pub fn parse(output: &str) -> Result<Option<BatteryInfo>, String> {
+    let mut lines = output.lines();
+    let first = lines.next().unwrap_or_default();
+    let supply = match first.split('\'').nth(1) {
+        Some("AC Power") => Supply::Ac,
+        Some("Battery Power") => Supply::Battery,
+        Some("UPS Power") => Supply::Ups,
+        _ => return Err(format!("Unexpected pmset output: {:?}", first)),
+    };
+
+    let batteries: Vec<&str> = lines.filter(|l| l.trim_start().starts_with('-')).collect();
+    // The machine's own battery, or the UPS on a desktop
+    let Some(line) = batteries
+        .iter()
+        .find(|l| l.contains("InternalBattery"))
+        .or(batteries.first())
+    else {
+        return Ok(None);
+    };
+    let mut info = parse_battery(line).ok_or_else(|| format!("Unexpected pmset battery line: {:?}", line.trim()))?;
+    info.supply = supply;
+    Ok(Some(info))
+}
+
+/// `-Name (id=…)<tab>85%; discharging; 5:32 remaining present: true`
+fn parse_battery(line: &str) -> Option<BatteryInfo> {
+    // The name may contain anything, so start from the percentage
+    let end = line.find("%;")?;
+    let start = line[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    let percentage = line[start..end].parse::<u8>().ok()?.min(100);
+
+    let mut fields = line[end + 2..].split(';').map(str::trim);
+    let state = match fields.next()? {
+        "charging" => ChargeState::Charging,
+        "finishing charge" => ChargeState::FinishingCharge,
+        "charged" => ChargeState::Charged,
+        "discharging" => ChargeState::Discharging,
+        "AC attached" => ChargeState::NotCharging,
+        _ => ChargeState::Unknown,
+    };
+    let minutes_remaining = fields
+        .next()
+        .and_then(|f| {
+            let (hours, minutes) = f.split(" remaining").next()?.split_once(':')?;
+            Some(hours.parse::<u32>().ok()? * 60 + minutes.parse::<u32>().ok()?)
+        });
+
+    Some(BatteryInfo {
+        percentage,
+        state,
+        minutes_remaining,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(percentage: u8, state: ChargeState, supply: Supply, minutes: Option<u32>) -> BatteryInfo {
+        BatteryInfo {
+            percentage,
+            state,
+            supply,
+            minutes_remaining: minutes,
+        }
+    }
+
+    #[test]
+    fn test_fixtures() {
+        use ChargeState::*;
+        use Supply::*;
+
+        let cases = [
+            (include_str!("../fixtures/pmset/discharging.txt"), info(85, Discharging, Battery, Some(332))),
+            (include_str!("../fixtures/pmset/discharging-no-estimate.txt"), info(90, Discharging, Battery, None)),
+            (include_str!("../fixtures/pmset/charging.txt"), info(62, Charging, Ac, Some(65))),
+            (include_str!("../fixtures/pmset/finishing-charge.txt"), info(97, FinishingCharge, Ac, Some(12))),
+            (include_str!("../fixtures/pmset/charged.txt"), info(100, Charged, Ac, Some(0))),
+            (include_str!("../fixtures/pmset/not-charging.txt"), info(80, NotCharging, Ac, None)),
+            (include_str!("../fixtures/pmset/older-macos.txt"), info(100, Charged, Ac, Some(0))),
+            // A laptop shows its own battery, a desktop the UPS
+            (include_str!("../fixtures/pmset/ups-laptop.txt"), info(100, NotCharging, Ups, None)),
+            (include_str!("../fixtures/pmset/ups-desktop.txt"), info(64, Discharging, Ups, None)),
+        ];
+        for (output, expected) in cases {
+            assert_eq!(parse(output), Ok(Some(expected)), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse(include_str!("../fixtures/pmset/no-battery.txt")), Ok(None));
+        assert!(parse("").is_err());
+        assert!(parse("Now drawing from 'AC Power'\n -InternalBattery-0\tcharged\n").is_err());
+    }
+}
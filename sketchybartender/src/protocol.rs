@@ -0,0 +1,116 @@
+//! Wire format shared by the daemon and sketchycli.
+//!
+//! Requests are single text lines (`<command> [args...]`). Every request is
+//! answered with exactly one line of JSON:
+//!
+//! ```text
+//! {"status":"ok"}
+//! {"status":"ok","payload":{...}}
+//! {"status":"error","message":"..."}
+//! {"status":"unknown-command","command":"..."}
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Reply sent by the daemon for every request line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum Response {
+    /// The command ran; some commands attach a JSON payload
+    Ok {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+    /// The command was recognised but failed
+    Error { message: String },
+    /// The daemon does not know the command
+    UnknownCommand { command: String },
+}
+
+#[allow(dead_code)] // Each binary only uses one side of the protocol
+impl Response {
+    pub fn ok() -> Self {
+        Response::Ok { payload: None }
+    }
+
+    pub fn with_payload(payload: Value) -> Self {
+        Response::Ok {
+            payload: Some(payload),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Response::Error {
+            message: message.into(),
+        }
+    }
+
+    /// Serialize to a single line (without the trailing newline)
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| {
+            format!(
+                "{{\"status\":\"error\",\"message\":\"failed to encode response: {}\"}}",
+                e
+            )
+        })
+    }
+
+    pub fn from_line(line: &str) -> Result<Self, String> {
+        serde_json::from_str(line.trim()).map_err(|e| format!("Malformed response: {}", e))
+    }
+
+    /// Process exit code sketchycli uses for this response
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Response::Ok { .. } => 0,
+            Response::Error { .. } => 1,
+            Response::UnknownCommand { .. } => 2,
+        }
+    }
+}
+
+impl From<Result<Option<Value>, String>> for Response {
+    fn from(result: Result<Option<Value>, String>) -> Self {
+        match result {
+            Ok(payload) => Response::Ok { payload },
+            Err(message) => Response::Error { message },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let responses = [
+            Response::ok(),
+            Response::with_payload(serde_json::json!({ "front_app": "Safari" })),
+            Response::error("brew not found"),
+            Response::UnknownCommand {
+                command: "on-nothing".to_string(),
+            },
+        ];
+        for response in responses {
+            let line = response.to_line();
+            assert!(!line.contains('\n'));
+            assert_eq!(Response::from_line(&line).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn test_wire_format() {
+        assert_eq!(Response::ok().to_line(), r#"{"status":"ok"}"#);
+        assert_eq!(
+            Response::error("boom").to_line(),
+            r#"{"status":"error","message":"boom"}"#
+        );
+        assert_eq!(Response::error("boom").exit_code(), 1);
+        assert_eq!(
+            Response::UnknownCommand { command: "x".into() }.exit_code(),
+            2
+        );
+    }
+}
@@ -1,31 +1,87 @@
+use std::fmt::{self, Write};
 use std::process::Command;
 use std::thread;
-use chrono::Local;
+use chrono::{DateTime, Local, Locale, TimeZone, Utc};
+
+use crate::config::ClockConfig;
+
+/// What the battery is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChargeState {
+    Charging,
+    /// Nearly full; charging slows down (macOS only)
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    FinishingCharge,
+    Charged,
+    Discharging,
+    /// On AC but held below full (optimized charging, a charge limit)
+    NotCharging,
+    #[default]
+    Unknown,
+}
+
+impl fmt::Display for ChargeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChargeState::Charging => "Charging",
+            ChargeState::FinishingCharge => "Finishing charge",
+            ChargeState::Charged => "Charged",
+            ChargeState::Discharging => "Discharging",
+            ChargeState::NotCharging => "Not charging",
+            ChargeState::Unknown => "Unknown",
+        })
+    }
+}
+
+/// What the machine is drawing power from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Supply {
+    Ac,
+    #[default]
+    Battery,
+    /// An uninterruptible power supply, i.e. the wall power is out (macOS only)
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Ups,
+}
 
 /// Battery information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct BatteryInfo {
     pub percentage: u8,
-    pub is_charging: bool,
+    pub state: ChargeState,
+    pub supply: Supply,
+    /// Minutes until empty (discharging) or full (charging), when there is an
+    /// estimate
+    pub minutes_remaining: Option<u32>,
 }
 
 impl BatteryInfo {
-    /// Get the appropriate icon for the battery state
-    pub fn icon(&self) -> &'static str {
-        match self.percentage {
-            90..=100 => "\u{f240}",
-            70..=89 => "\u{f241}",
-            40..=69 => "\u{f242}",
-            10..=39 => "\u{f243}",
-            _ => "\u{f244}",
+    /// Plugged into the wall (a UPS doesn't count)
+    pub fn on_ac(&self) -> bool {
+        self.supply == Supply::Ac
+    }
+
+    /// Time until empty or full as "H:MM"; nothing once charged
+    pub fn remaining(&self) -> Option<String> {
+        match (self.state, self.minutes_remaining) {
+            (ChargeState::Charged | ChargeState::NotCharging, _) => None,
+            (_, Some(minutes)) => Some(format!("{}:{:02}", minutes / 60, minutes % 60)),
+            (_, None) => None,
         }
     }
 
+    /// Get the appropriate icon for the battery state, from the configured set
+    pub fn icon(&self, config: &crate::config::Config) -> &'static str {
+        let charging = matches!(self.state, ChargeState::Charging | ChargeState::FinishingCharge);
+        let steps = config.battery.steps.unwrap_or(config.icons.battery_steps());
+        config.icons.battery(self.percentage, charging, steps)
+    }
+
     /// Get the icon color based on charging state, using colors from config
     pub fn icon_color<'a>(&self, config: &'a crate::config::Config) -> &'a str {
-        if self.is_charging {
+        if self.on_ac() {
             &config.battery_charging_color // Charging
-        } else if self.percentage <= 10 {
+        } else if self.percentage <= config.battery.critical {
             &config.battery_low_color // Critically low
         } else {
             &config.battery_normal_color // Discharging normally
@@ -37,35 +93,6 @@ impl BatteryInfo {
     }
 }
 
-/// Get current battery information
-/// If power_source is provided (from sketchybar event), use it directly instead of querying pmset
-pub fn get_battery(power_source: Option<String>) -> Option<BatteryInfo> {
-    let output = Command::new("pmset")
-        .args(["-g", "batt"])
-        .output()
-        .ok()?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // Parse percentage - look for word containing '%' (e.g., "26%;" or "100%")
-    let percentage = stdout
-        .split_whitespace()
-        .find(|s| s.contains('%'))
-        .and_then(|s| {
-            // Extract digits before the '%' sign
-            s.split('%').next()?.parse::<u8>().ok()
-        })?;
-
-    // Check if charging - use provided power_source if available, otherwise parse from pmset output
-    let is_charging = if let Some(source) = power_source {
-        source == "AC"
-    } else {
-        stdout.contains("AC Power")
-    };
-
-    Some(BatteryInfo { percentage, is_charging })
-}
-
 /// Volume information
 #[derive(Debug, Clone)]
 pub struct VolumeInfo {
@@ -74,16 +101,9 @@ pub struct VolumeInfo {
 }
 
 impl VolumeInfo {
-    /// Get the appropriate icon for the volume level
-    pub fn icon(&self) -> &'static str {
-        if self.muted || self.percentage == 0 {
-            return "󰖁";
-        }
-        match self.percentage {
-            60..=100 => "󰕾",
-            30..=59 => "󰖀",
-            _ => "󰕿",
-        }
+    /// Get the appropriate icon for the volume level, from the configured set
+    pub fn icon(&self, config: &crate::config::Config) -> &'static str {
+        config.icons.volume(self.percentage, self.muted)
     }
 }
 
@@ -110,10 +130,65 @@ pub fn get_volume() -> Option<VolumeInfo> {
     Some(VolumeInfo { percentage, muted })
 }
 
-/// Get current time formatted as DD/MM HH:MM
-pub fn get_clock() -> String {
-    let now = Local::now();
-    now.format("%d/%m %H:%M").to_string()
+/// Text shown by the clock item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockText {
+    /// Local time
+    pub time: String,
+    /// One "<label> <time>" per extra zone
+    pub zones: Vec<String>,
+}
+
+/// Format `time` in `locale`; an invalid format falls back to the default
+fn format_time<Tz: TimeZone>(time: &DateTime<Tz>, format: &str, locale: Locale) -> String
+where
+    Tz::Offset: fmt::Display,
+{
+    let mut text = String::new();
+    if write!(text, "{}", time.format_localized(format, locale)).is_err() {
+        text = time.format(&ClockConfig::default().format).to_string();
+    }
+    text
+}
+
+/// The clock's text at `now`
+pub fn format_clock(now: DateTime<Utc>, config: &ClockConfig) -> ClockText {
+    let locale = Locale::try_from(config.locale.as_str()).unwrap_or(Locale::en_US);
+    let zones = config
+        .zones
+        .iter()
+        .filter_map(|zone| {
+            let tz: chrono_tz::Tz = zone.tz.parse().ok()?;
+            let time = format_time(&now.with_timezone(&tz), &config.zone_format, locale);
+            Some(format!("{} {}", zone.label, time).trim().to_string())
+        })
+        .collect();
+    ClockText {
+        time: format_time(&now.with_timezone(&Local), &config.format, locale),
+        zones,
+    }
+}
+
+/// Get the clock's text now
+pub fn get_clock(config: &ClockConfig) -> ClockText {
+    format_clock(Utc::now(), config)
+}
+
+/// Whether a strftime format shows seconds, so the clock has to tick every
+/// second rather than every minute
+pub fn format_needs_seconds(format: &str) -> bool {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        // Skip padding flags, widths and the `:` of `%:z`
+        let spec = chars.find(|c| !matches!(c, '-' | '_' | '0'..='9' | '^' | '#' | ':' | '.'));
+        if matches!(spec, Some('S' | 'T' | 'X' | 'r' | 's' | 'c' | '+' | 'f')) {
+            return true;
+        }
+    }
+    false
 }
 
 
@@ -195,50 +270,22 @@ pub struct SystemInfo {
 /// `busy` = user + system + nice; `total` = busy + idle.
 pub type CpuTicks = (u64, u64);
 
-/// Read the kernel's cumulative CPU-tick counters via `host_statistics`.
-///
-/// This is the same `HOST_CPU_LOAD_INFO` data Activity Monitor derives its CPU
-/// figures from. A single call costs microseconds and — unlike `top` — spawns
-/// no process and runs no sampling pass, so it doesn't pollute its own
-/// measurement window (which is what made `top` over-report `sys`).
-pub fn read_cpu_ticks() -> Option<CpuTicks> {
-    use std::mem::MaybeUninit;
-
-    let mut info = MaybeUninit::<libc::host_cpu_load_info>::uninit();
-    let mut count = libc::HOST_CPU_LOAD_INFO_COUNT;
-    let kr = unsafe {
-        libc::host_statistics(
-            mach2::mach_init::mach_host_self(),
-            libc::HOST_CPU_LOAD_INFO,
-            info.as_mut_ptr() as libc::host_info_t,
-            &mut count,
-        )
-    };
-    if kr != libc::KERN_SUCCESS {
-        return None;
-    }
-    let info = unsafe { info.assume_init() };
-    let ticks = &info.cpu_ticks;
-    let user = ticks[libc::CPU_STATE_USER as usize] as u64;
-    let sys = ticks[libc::CPU_STATE_SYSTEM as usize] as u64;
-    let nice = ticks[libc::CPU_STATE_NICE as usize] as u64;
-    let idle = ticks[libc::CPU_STATE_IDLE as usize] as u64;
-    let busy = user + sys + nice;
-    Some((busy, busy + idle))
+/// Memory in use and installed, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryInfo {
+    pub used_bytes: u64,
+    pub total_bytes: u64,
 }
 
-/// Get current CPU and RAM usage.
+/// CPU and RAM usage from two CPU tick snapshots and a memory reading.
 ///
-/// CPU is measured as the delta between two `read_cpu_ticks()` snapshots. The
-/// caller passes the previous snapshot (from the last refresh) and receives the
-/// current one back, so the busy% is averaged over the whole refresh interval —
-/// matching how Activity Monitor's CPU graph reads. The first call (no `prev`)
-/// reports 0% and just returns the bootstrap snapshot.
-pub fn get_system_info(prev_cpu: Option<CpuTicks>) -> (SystemInfo, Option<CpuTicks>) {
+/// CPU is measured as the delta between the snapshot from the last refresh
+/// and the current one, so the busy% is averaged over the whole refresh
+/// interval — matching how Activity Monitor's CPU graph reads. Without a
+/// previous snapshot (the first refresh) it reports 0%.
+pub fn system_info(prev_cpu: Option<CpuTicks>, cur_cpu: Option<CpuTicks>, memory: MemoryInfo) -> SystemInfo {
     let mut info = SystemInfo::default();
 
-    // CPU usage from the tick-counter delta since the previous refresh.
-    let cur_cpu = read_cpu_ticks();
     if let (Some((prev_busy, prev_total)), Some((cur_busy, cur_total))) = (prev_cpu, cur_cpu) {
         let busy = cur_busy.saturating_sub(prev_busy);
         let total = cur_total.saturating_sub(prev_total);
@@ -247,77 +294,14 @@ pub fn get_system_info(prev_cpu: Option<CpuTicks>) -> (SystemInfo, Option<CpuTic
         }
     }
 
-    // Get RAM usage the way Activity Monitor reports it.
-    //
-    // macOS keeps almost nothing truly free — inactive, speculative and
-    // purgeable pages are all reclaimable and are NOT counted as "used" by
-    // Activity Monitor. We derive Activity Monitor's "Memory Used" =
-    // App Memory + Wired + Compressed from the kernel's vm statistics:
-    //   used = (active + wired + compressor - purgeable) * page_size
-    let (used_bytes, total_bytes) = get_memory_used_total();
-    if total_bytes > 0 {
-        info.ram_percentage = ((used_bytes as f64 / total_bytes as f64) * 100.0).round() as u8;
-        info.ram_used_gb = (used_bytes as f64 / 1_073_741_824.0) as f32;
-        info.ram_total_gb = (total_bytes as f64 / 1_073_741_824.0) as f32;
-    }
-
-    (info, cur_cpu)
-}
-
-/// Total physical memory in bytes, from `sysctlbyname("hw.memsize")`.
-/// This value is invariant for the life of the process.
-fn total_memory_bytes() -> u64 {
-    let mut value: u64 = 0;
-    let mut size = std::mem::size_of::<u64>();
-    let name = b"hw.memsize\0";
-    let rc = unsafe {
-        libc::sysctlbyname(
-            name.as_ptr() as *const libc::c_char,
-            &mut value as *mut u64 as *mut libc::c_void,
-            &mut size,
-            std::ptr::null_mut(),
-            0,
-        )
-    };
-    if rc == 0 {
-        value
-    } else {
-        0
-    }
-}
-
-/// Compute (used_bytes, total_bytes) matching Activity Monitor's "Memory Used",
-/// reading the kernel's VM statistics directly via `host_statistics64` — no
-/// `vm_stat`/`sysctl` subprocess.
-fn get_memory_used_total() -> (u64, u64) {
-    use std::mem::MaybeUninit;
-
-    let total_bytes = total_memory_bytes();
-
-    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
-    let page_size = if page_size > 0 { page_size as u64 } else { 4096 };
-
-    let mut info = MaybeUninit::<libc::vm_statistics64>::uninit();
-    let mut count = libc::HOST_VM_INFO64_COUNT;
-    let kr = unsafe {
-        libc::host_statistics64(
-            mach2::mach_init::mach_host_self(),
-            libc::HOST_VM_INFO64,
-            info.as_mut_ptr() as libc::host_info64_t,
-            &mut count,
-        )
-    };
-    if kr != libc::KERN_SUCCESS {
-        return (0, total_bytes);
+    if memory.total_bytes > 0 {
+        let (used, total) = (memory.used_bytes as f64, memory.total_bytes as f64);
+        info.ram_percentage = ((used / total) * 100.0).round() as u8;
+        info.ram_used_gb = (used / 1_073_741_824.0) as f32;
+        info.ram_total_gb = (total / 1_073_741_824.0) as f32;
     }
-    let vm = unsafe { info.assume_init() };
 
-    let used_pages = (vm.active_count as u64
-        + vm.wire_count as u64
-        + vm.compressor_page_count as u64)
-        .saturating_sub(vm.purgeable_count as u64);
-
-    (used_pages * page_size, total_bytes)
+    info
 }
 
 /// Microsoft Teams notification information
@@ -404,35 +388,114 @@ return "0"
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{ClockZone, Config};
+
+    fn battery(percentage: u8, state: ChargeState) -> BatteryInfo {
+        BatteryInfo { percentage, state, ..Default::default() }
+    }
 
     #[test]
     fn test_battery_icons() {
-        let high = BatteryInfo { percentage: 95, is_charging: false };
-        assert_eq!(high.icon(), "󱊣");
+        let config = Config::default();
+        let high = battery(95, ChargeState::Discharging);
+        assert_eq!(high.icon(&config), "󱊣");
 
-        let is_charging = BatteryInfo { percentage: 50, is_charging: true };
-        assert_eq!(is_charging.icon(), "\u{f0e7}"); // nf-fa-bolt
+        let is_charging = BatteryInfo { supply: Supply::Ac, ..battery(50, ChargeState::Charging) };
+        assert_eq!(is_charging.icon(&config), "\u{f0e7}"); // nf-fa-bolt
+
+        let low = battery(5, ChargeState::Discharging);
+        assert_eq!(low.icon(&config), "󰂎");
+    }
 
-        let low = BatteryInfo { percentage: 5, is_charging: false };
-        assert_eq!(low.icon(), "󰂎");
+    #[test]
+    fn test_battery_remaining() {
+        let info = |state, minutes| BatteryInfo { minutes_remaining: minutes, ..battery(50, state) };
+        assert_eq!(info(ChargeState::Discharging, Some(332)).remaining().as_deref(), Some("5:32"));
+        assert_eq!(info(ChargeState::Charging, Some(5)).remaining().as_deref(), Some("0:05"));
+        assert_eq!(info(ChargeState::Discharging, None).remaining(), None);
+        assert_eq!(info(ChargeState::Charged, Some(0)).remaining(), None);
     }
 
     #[test]
     fn test_volume_icons() {
+        let config = Config::default();
         let high = VolumeInfo { percentage: 80, muted: false };
-        assert_eq!(high.icon(), "\u{f240}");
+        assert_eq!(high.icon(&config), "\u{f240}");
 
         let muted = VolumeInfo { percentage: 80, muted: true };
-        assert_eq!(muted.icon(), "󰖁");
+        assert_eq!(muted.icon(&config), "󰖁");
 
         let zero = VolumeInfo { percentage: 0, muted: false };
-        assert_eq!(zero.icon(), "\u{f244}");
+        assert_eq!(zero.icon(&config), "\u{f244}");
+    }
+
+    #[test]
+    fn test_system_info() {
+        let memory = MemoryInfo { used_bytes: 6 << 30, total_bytes: 16 << 30 };
+        let info = system_info(Some((100, 1000)), Some((350, 2000)), memory);
+        assert_eq!(info.cpu_percentage, 25);
+        assert_eq!(info.ram_percentage, 38);
+        assert_eq!(info.ram_used_gb, 6.0);
+        assert_eq!(info.ram_total_gb, 16.0);
+
+        // Nothing to compare against yet, or no memory reading
+        let info = system_info(None, Some((350, 2000)), MemoryInfo::default());
+        assert_eq!((info.cpu_percentage, info.ram_percentage), (0, 0));
+        // Counters that didn't move
+        assert_eq!(system_info(Some((1, 2)), Some((1, 2)), memory).cpu_percentage, 0);
+    }
+
+    #[test]
+    fn test_format_needs_seconds() {
+        assert!(!format_needs_seconds(&ClockConfig::default().format));
+        assert!(!format_needs_seconds("%a %-d %b %H:%M %%S"));
+        assert!(format_needs_seconds("%H:%M:%S"));
+        assert!(format_needs_seconds("%T"));
+        assert!(format_needs_seconds("%-S"));
+        assert!(format_needs_seconds("%.3f"));
     }
 
     #[test]
     fn test_clock() {
-        let clock = get_clock();
-        assert!(clock.contains('/'));
-        assert!(clock.contains(':'));
+        let clock = get_clock(&ClockConfig::default());
+        assert!(clock.time.contains('/'));
+        assert!(clock.time.contains(':'));
+        assert!(clock.zones.is_empty());
+    }
+
+    fn clock_config(format: &str, locale: &str) -> ClockConfig {
+        ClockConfig {
+            format: format.to_string(),
+            locale: locale.to_string(),
+            zones: vec![
+                ClockZone { label: "NYC".to_string(), tz: "America/New_York".to_string() },
+                ClockZone { label: "BLR".to_string(), tz: "Asia/Kolkata".to_string() },
+                ClockZone { label: "??".to_string(), tz: "Nowhere/Special".to_string() },
+            ],
+            ..ClockConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_clock_zones() {
+        // 2024-03-14 13:14 UTC
+        let now = Utc.with_ymd_and_hms(2024, 3, 14, 13, 14, 0).unwrap();
+        let clock = format_clock(now, &clock_config("%Y", "en_US"));
+        assert_eq!(clock.time, "2024");
+        assert_eq!(clock.zones, vec!["NYC 09:14", "BLR 18:44"]);
+    }
+
+    #[test]
+    fn test_clock_locale_and_tokens() {
+        // Midday, so the date is the same in every local time zone
+        let now = Utc.with_ymd_and_hms(2024, 3, 14, 12, 0, 0).unwrap();
+        let mut config = clock_config("%A %-d %B, week %V, day %j", "de_DE");
+        config.zone_format = "%a %H:%M".to_string();
+        let clock = format_clock(now, &config);
+        assert_eq!(clock.time, "Donnerstag 14 März, week 11, day 074");
+        assert_eq!(clock.zones[0], "NYC Do 08:00");
+
+        config.locale = "fr_FR".to_string();
+        assert_eq!(format_clock(now, &config).time, "jeudi 14 mars, week 11, day 074");
     }
 }
@@ -0,0 +1,275 @@
+//! Reconnect logic for the sketchybar mach port
+//!
+//! The bootstrap lookup result is cached, but a cached port dies whenever
+//! sketchybar restarts (`sketchybar --reload`, a crash, an upgrade). When a
+//! send reports a dead destination, `Connection` drops the port, looks the
+//! service up again with backoff and resends. The caller is told the
+//! connection was re-established so it can re-push the full bar state.
+//!
+//! The mach specifics live behind `Transport` so this can be tested without
+//! a real mach port. Off macOS only `Delivery` is used.
+#![cfg_attr(not(target_os = "macos"), allow(dead_code))]
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Delays between lookup attempts after the port died
+pub const DEFAULT_BACKOFF: &[Duration] = &[
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(200),
+    Duration::from_millis(400),
+    Duration::from_millis(800),
+];
+
+/// Why a send failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// The destination port no longer exists; look it up again
+    DeadPort(String),
+    /// Any other failure; retrying with a fresh port won't help
+    Other(String),
+}
+
+/// How a successful send was delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Sent over the existing connection
+    Sent,
+    /// Sent after the connection had to be re-established, so the receiver
+    /// has probably lost everything sent before
+    Reconnected,
+}
+
+/// The operations `Connection` needs from the underlying IPC mechanism
+pub trait Transport {
+    type Port: Copy;
+
+    /// Look up the service and return a port to send to
+    fn lookup(&self) -> Result<Self::Port, String>;
+
+    /// Send a message to `port`
+    fn send(&self, port: Self::Port, message: &[u8]) -> Result<(), SendError>;
+
+    /// Release a port that is no longer used
+    fn release(&self, _port: Self::Port) {}
+}
+
+/// Cached port plus whether an earlier connection was lost
+struct PortState<P> {
+    port: Option<P>,
+    /// A port died and no send has succeeded since
+    lost: bool,
+}
+
+/// A cached port that is transparently re-established when it dies
+pub struct Connection<T: Transport> {
+    transport: T,
+    state: Mutex<PortState<T::Port>>,
+    backoff: &'static [Duration],
+    sleep: fn(Duration),
+}
+
+impl<T: Transport> Connection<T> {
+    pub const fn new(transport: T, backoff: &'static [Duration], sleep: fn(Duration)) -> Self {
+        Self {
+            transport,
+            state: Mutex::new(PortState {
+                port: None,
+                lost: false,
+            }),
+            backoff,
+            sleep,
+        }
+    }
+
+    /// Send `message`, reconnecting if the cached port turns out to be dead.
+    ///
+    /// Returns `Delivery::Reconnected` for the first successful send after a
+    /// port died, even if reconnecting took more than one call.
+    pub fn send(&self, message: &[u8]) -> Result<Delivery, String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let port = match state.port {
+            Some(port) => port,
+            None => {
+                let port = self.transport.lookup()?;
+                state.port = Some(port);
+                port
+            }
+        };
+
+        match self.transport.send(port, message) {
+            Ok(()) => return Ok(self.delivered(&mut state)),
+            Err(SendError::Other(e)) => return Err(e),
+            Err(SendError::DeadPort(e)) => {
+                eprintln!("[MACH] Port is dead ({}), reconnecting", e);
+                state.port = None;
+                state.lost = true;
+                self.transport.release(port);
+            }
+        }
+
+        let port = self.lookup_with_backoff()?;
+        state.port = Some(port);
+
+        match self.transport.send(port, message) {
+            Ok(()) => Ok(self.delivered(&mut state)),
+            Err(SendError::DeadPort(e)) => {
+                state.port = None;
+                self.transport.release(port);
+                Err(e)
+            }
+            Err(SendError::Other(e)) => Err(e),
+        }
+    }
+
+    fn delivered(&self, state: &mut PortState<T::Port>) -> Delivery {
+        if std::mem::take(&mut state.lost) {
+            Delivery::Reconnected
+        } else {
+            Delivery::Sent
+        }
+    }
+
+    fn lookup_with_backoff(&self) -> Result<T::Port, String> {
+        let mut last_error = String::from("no lookup attempted");
+        for delay in self.backoff {
+            (self.sleep)(*delay);
+            match self.transport.lookup() {
+                Ok(port) => return Ok(port),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(format!(
+            "Failed to reconnect after {} attempts: {}",
+            self.backoff.len(),
+            last_error
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Scripted transport: each lookup/send pops the next result
+    #[derive(Default)]
+    struct FakeTransport {
+        lookups: RefCell<VecDeque<Result<u32, String>>>,
+        sends: RefCell<VecDeque<Result<(), SendError>>>,
+        sent_to: RefCell<Vec<u32>>,
+        released: RefCell<Vec<u32>>,
+    }
+
+    impl FakeTransport {
+        fn new(lookups: Vec<Result<u32, String>>, sends: Vec<Result<(), SendError>>) -> Self {
+            Self {
+                lookups: RefCell::new(lookups.into()),
+                sends: RefCell::new(sends.into()),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Transport for FakeTransport {
+        type Port = u32;
+
+        fn lookup(&self) -> Result<u32, String> {
+            self.lookups.borrow_mut().pop_front().expect("unexpected lookup")
+        }
+
+        fn send(&self, port: u32, _message: &[u8]) -> Result<(), SendError> {
+            self.sent_to.borrow_mut().push(port);
+            self.sends.borrow_mut().pop_front().expect("unexpected send")
+        }
+
+        fn release(&self, port: u32) {
+            self.released.borrow_mut().push(port);
+        }
+    }
+
+    const BACKOFF: &[Duration] = &[
+        Duration::from_millis(1),
+        Duration::from_millis(2),
+        Duration::from_millis(4),
+    ];
+
+    fn connection(transport: FakeTransport) -> Connection<FakeTransport> {
+        Connection::new(transport, BACKOFF, |_| {})
+    }
+
+    fn dead() -> Result<(), SendError> {
+        Err(SendError::DeadPort("MACH_SEND_INVALID_DEST".to_string()))
+    }
+
+    #[test]
+    fn test_port_is_looked_up_once() {
+        let conn = connection(FakeTransport::new(vec![Ok(7)], vec![Ok(()), Ok(())]));
+        assert_eq!(conn.send(b"a"), Ok(Delivery::Sent));
+        assert_eq!(conn.send(b"b"), Ok(Delivery::Sent));
+        assert_eq!(*conn.transport.sent_to.borrow(), vec![7, 7]);
+    }
+
+    #[test]
+    fn test_dead_port_reconnects_and_resends() {
+        let conn = connection(FakeTransport::new(vec![Ok(7), Ok(8)], vec![dead(), Ok(()), Ok(())]));
+        assert_eq!(conn.send(b"a"), Ok(Delivery::Reconnected));
+        assert_eq!(conn.send(b"b"), Ok(Delivery::Sent));
+        assert_eq!(*conn.transport.sent_to.borrow(), vec![7, 8, 8]);
+        assert_eq!(*conn.transport.released.borrow(), vec![7]);
+    }
+
+    #[test]
+    fn test_lookup_retries_with_backoff() {
+        let conn = connection(FakeTransport::new(
+            vec![Ok(7), Err("not running".into()), Err("not running".into()), Ok(9)],
+            vec![dead(), Ok(())],
+        ));
+        assert_eq!(conn.send(b"a"), Ok(Delivery::Reconnected));
+        assert_eq!(*conn.transport.sent_to.borrow(), vec![7, 9]);
+    }
+
+    #[test]
+    fn test_gives_up_after_backoff_and_tries_again_next_send() {
+        let conn = connection(FakeTransport::new(
+            vec![Ok(7), Err("a".into()), Err("b".into()), Err("c".into()), Ok(9)],
+            vec![dead(), Ok(())],
+        ));
+        let err = conn.send(b"a").unwrap_err();
+        assert!(err.contains("3 attempts"), "{}", err);
+        assert!(err.contains('c'), "{}", err);
+
+        // No port is cached, so the next send starts with a fresh lookup and
+        // still reports the reconnect
+        assert_eq!(conn.send(b"b"), Ok(Delivery::Reconnected));
+        assert_eq!(*conn.transport.sent_to.borrow(), vec![7, 9]);
+    }
+
+    #[test]
+    fn test_other_errors_keep_the_port() {
+        let conn = connection(FakeTransport::new(
+            vec![Ok(7)],
+            vec![Err(SendError::Other("too large".into())), Ok(())],
+        ));
+        assert_eq!(conn.send(b"a"), Err("too large".to_string()));
+        assert_eq!(conn.send(b"b"), Ok(Delivery::Sent));
+        assert!(conn.transport.released.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_backoff_delays_are_used_in_order() {
+        thread_local! {
+            static SLEPT: RefCell<Vec<Duration>> = const { RefCell::new(Vec::new()) };
+        }
+        let conn = Connection::new(
+            FakeTransport::new(vec![Ok(7), Err("a".into()), Ok(8)], vec![dead(), Ok(())]),
+            BACKOFF,
+            |d| SLEPT.with(|s| s.borrow_mut().push(d)),
+        );
+        conn.send(b"a").unwrap();
+        SLEPT.with(|s| assert_eq!(*s.borrow(), BACKOFF[..2].to_vec()));
+    }
+}
@@ -0,0 +1,122 @@
+//! Where readings come from
+//!
+//! A `Provider` reads one kind of snapshot from the system. CPU, memory and
+//! battery each have a macOS and a Linux backend; the `Registry` holds the
+//! ones for this machine, and the scheduled jobs read through it.
+
+#[cfg(any(not(target_os = "macos"), test))]
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::providers::{BatteryInfo, CpuTicks, MemoryInfo};
+#[cfg(any(not(target_os = "macos"), test))]
+use crate::linux;
+#[cfg(target_os = "macos")]
+use crate::macos;
+
+pub trait Provider: Send {
+    type Snapshot;
+
+    /// Where the readings come from, for `dump-state`
+    fn backend(&self) -> &'static str;
+
+    fn read(&mut self) -> Result<Self::Snapshot, String>;
+}
+
+/// A provider shared between the jobs and socket commands that read it
+pub struct Slot<T>(Mutex<Box<dyn Provider<Snapshot = T>>>);
+
+impl<T> Slot<T> {
+    pub fn new(provider: impl Provider<Snapshot = T> + 'static) -> Self {
+        Self(Mutex::new(Box::new(provider)))
+    }
+
+    pub fn read(&self) -> Result<T, String> {
+        self.0
+            .lock()
+            .map_err(|_| "Provider lock poisoned".to_string())?
+            .read()
+    }
+
+    pub fn backend(&self) -> &'static str {
+        self.0.lock().map(|p| p.backend()).unwrap_or("poisoned")
+    }
+}
+
+/// One provider per kind of reading
+pub struct Registry {
+    pub cpu: Slot<CpuTicks>,
+    pub memory: Slot<MemoryInfo>,
+    /// None on a machine without a battery
+    pub battery: Slot<Option<BatteryInfo>>,
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registry")
+            .field("cpu", &self.cpu.backend())
+            .field("memory", &self.memory.backend())
+            .field("battery", &self.battery.backend())
+            .finish()
+    }
+}
+
+impl Registry {
+    /// The backends for the platform this was built for
+    pub fn native() -> Self {
+        #[cfg(target_os = "macos")]
+        return Self::macos();
+        #[cfg(not(target_os = "macos"))]
+        Self::linux("/")
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn macos() -> Self {
+        Self {
+            cpu: Slot::new(macos::MachCpu),
+            memory: Slot::new(macos::MachMemory),
+            battery: Slot::new(macos::Pmset),
+        }
+    }
+
+    /// Linux backends reading `/proc` and `/sys` below `root`
+    #[cfg(any(not(target_os = "macos"), test))]
+    pub fn linux(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            cpu: Slot::new(linux::ProcStat { root: root.clone() }),
+            memory: Slot::new(linux::ProcMeminfo { root: root.clone() }),
+            battery: Slot::new(linux::PowerSupply { root }),
+        }
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "cpu": self.cpu.backend(),
+            "memory": self.memory.backend(),
+            "battery": self.battery.backend(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn test_linux_registry_reads_below_root() {
+        let root = env::temp_dir().join(format!("sketchybartender-registry-{}", std::process::id()));
+        fs::create_dir_all(root.join("proc")).unwrap();
+        fs::write(root.join("proc/stat"), "cpu  30 0 10 60 0 0 0 0 0 0\n").unwrap();
+        fs::write(root.join("proc/meminfo"), "MemTotal: 2048 kB\nMemAvailable: 1024 kB\n").unwrap();
+
+        let registry = Registry::linux(&root);
+        assert_eq!(registry.cpu.read(), Ok((40, 100)));
+        assert_eq!(registry.memory.read(), Ok(MemoryInfo { used_bytes: 1 << 20, total_bytes: 2 << 20 }));
+        assert_eq!(registry.battery.read(), Ok(None));
+        assert_eq!(registry.snapshot()["cpu"], "/proc/stat");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+//! Config hot reload
+//!
+//! The config files are polled for changes by the `config-watch` job, and
+//! `reload-config` reloads them on demand. Either way the new config is
+//! swapped into `DaemonState`, the scheduler picks up the new intervals, and
+//! every config-dependent item is rendered again.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+use crate::handlers::{self, DaemonState, HandlerResult};
+
+/// How often the config file's modification time is checked
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Load the config file again and apply it
+pub fn reload_config(state: &Arc<Mutex<DaemonState>>) -> HandlerResult {
+    let config = Config::reload()?;
+    state
+        .lock()
+        .map_err(|_| "Daemon state lock poisoned".to_string())?
+        .replace_config(config);
+    eprintln!("Reloaded config from {:?}", Config::get_config_path());
+    handlers::refresh_all(state)
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Each layer file with its modification time
+fn snapshot() -> Vec<(Option<SystemTime>, PathBuf)> {
+    Config::layer_paths()
+        .into_iter()
+        .map(|path| (modified(&path), path))
+        .collect()
+}
+
+/// Scheduled task that reloads the config whenever a layer file's
+/// modification time changes, or a file of another format starts taking
+/// precedence
+pub fn config_watcher(state: Arc<Mutex<DaemonState>>) -> impl FnMut(&Config) -> HandlerResult + Send {
+    let mut last = snapshot();
+    move |_| {
+        let current = snapshot();
+        // The user file comes first; without it there's nothing to reload
+        let user_file_exists = current.first().is_some_and(|(modified, _)| modified.is_some());
+        if !user_file_exists || current == last {
+            return Ok(());
+        }
+        last = current;
+        reload_config(&state).map_err(|e| format!("Failed to reload config: {}", e))
+    }
+}
@@ -0,0 +1,241 @@
+//! Render cache: the last values pushed to each sketchybar item
+//!
+//! Every successful `--set` is recorded here. `SketchybarBatch` diffs new
+//! values against it to drop no-op updates, `dump-state` reports it, and it
+//! is replayed when sketchybar has lost its state (after a reconnect, or on
+//! demand through `repush_all`).
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+
+use crate::batch::{BatchCommand, SketchybarBatch};
+use crate::sink;
+
+/// Last pushed state of a single item
+#[derive(Debug, Clone)]
+pub struct RenderedItem {
+    /// Last value sent for each property
+    pub props: BTreeMap<String, String>,
+    /// When the item was last refreshed
+    pub last_refresh: DateTime<Local>,
+}
+
+/// Last pushed state of every item, keyed by item name
+#[derive(Debug, Default)]
+pub struct RenderCache {
+    items: BTreeMap<String, RenderedItem>,
+    /// Items added by the daemon, with their bar position
+    added: BTreeMap<String, String>,
+    /// Items of the last `--reorder`, in bar order
+    order: Option<Vec<String>>,
+}
+
+impl RenderCache {
+    pub const fn new() -> Self {
+        Self {
+            items: BTreeMap::new(),
+            added: BTreeMap::new(),
+            order: None,
+        }
+    }
+
+    /// Record that `props` were pushed to `item` at `at`
+    pub fn record(&mut self, item: &str, props: &[(String, String)], at: DateTime<Local>) {
+        let entry = self
+            .items
+            .entry(item.to_string())
+            .or_insert_with(|| RenderedItem {
+                props: BTreeMap::new(),
+                last_refresh: at,
+            });
+        for (key, value) in props {
+            entry.props.insert(key.clone(), value.clone());
+        }
+        entry.last_refresh = at;
+    }
+
+    /// Forget everything about `item`
+    pub fn forget(&mut self, item: &str) {
+        self.items.remove(item);
+        self.added.remove(item);
+        if let Some(order) = &mut self.order {
+            order.retain(|i| i != item);
+        }
+    }
+
+    /// Record every command in `batch` as pushed now
+    pub fn record_batch(&mut self, batch: &SketchybarBatch) {
+        let now = Local::now();
+        for command in batch.commands() {
+            match command {
+                BatchCommand::Add { item, position } => {
+                    self.added.insert(item.clone(), position.clone());
+                }
+                BatchCommand::Remove { item } => self.forget(item),
+                BatchCommand::Set { item, props } => self.record(item, props, now),
+                BatchCommand::Reorder { items } => self.order = Some(items.clone()),
+                BatchCommand::Animate { .. } => {}
+            }
+        }
+    }
+
+    /// Whether `item` was added through the cache and not removed since
+    pub fn is_added(&self, item: &str) -> bool {
+        self.added.contains_key(item)
+    }
+
+    /// Items of the last `--reorder` pushed
+    pub fn order(&self) -> Option<&[String]> {
+        self.order.as_deref()
+    }
+
+    #[allow(dead_code)] // Used in tests
+    pub fn get(&self, item: &str) -> Option<&RenderedItem> {
+        self.items.get(item)
+    }
+
+    /// Last value pushed for `key` on `item`
+    pub fn value(&self, item: &str, key: &str) -> Option<&str> {
+        self.items
+            .get(item)
+            .and_then(|i| i.props.get(key))
+            .map(String::as_str)
+    }
+
+    /// A batch that adds every added item and sets every cached property again
+    pub fn replay_batch(&self) -> SketchybarBatch {
+        let mut batch = SketchybarBatch::new();
+        for (name, position) in &self.added {
+            batch.add(name, position);
+        }
+        for (name, item) in &self.items {
+            let props: Vec<(&str, &str)> = item
+                .props
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            batch.set(name, &props);
+        }
+        if let Some(order) = &self.order {
+            let items: Vec<&str> = order.iter().map(String::as_str).collect();
+            batch.reorder(&items);
+        }
+        batch
+    }
+
+    /// JSON view of every item, relative to `now`
+    pub fn snapshot(&self, now: DateTime<Local>) -> Value {
+        let items: serde_json::Map<String, Value> = self
+            .items
+            .iter()
+            .map(|(name, item)| {
+                let age = now.signed_duration_since(item.last_refresh).num_seconds().max(0);
+                (
+                    name.clone(),
+                    json!({
+                        "props": item.props,
+                        "last_refresh": item.last_refresh.to_rfc3339(),
+                        "age_secs": age,
+                    }),
+                )
+            })
+            .collect();
+        Value::Object(items)
+    }
+}
+
+/// Process-wide cache used by `SketchybarBatch::execute`
+static RENDER_CACHE: Mutex<RenderCache> = Mutex::new(RenderCache::new());
+
+/// Run `f` with exclusive access to the process-wide cache.
+///
+/// The lock is held for the whole call so diffing, sending and recording a
+/// batch can't interleave with another thread's batch.
+pub fn with_cache<R>(f: impl FnOnce(&mut RenderCache) -> R) -> R {
+    let mut cache = RENDER_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut cache)
+}
+
+/// JSON view of everything pushed so far
+pub fn snapshot() -> Value {
+    with_cache(|cache| cache.snapshot(Local::now()))
+}
+
+/// Send the full last-known state of every item again, regardless of what
+/// the cache says is already on the bar (e.g. after sketchybar restarted)
+pub fn repush_all() -> Result<(), std::io::Error> {
+    with_cache(|cache| cache.replay_batch().send_to(sink::current().as_ref()).map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn props(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_merges_props() {
+        let t0 = Local::now();
+        let mut cache = RenderCache::new();
+        cache.record("battery", &props(&[("icon", "A"), ("label", "50%")]), t0);
+        cache.record("battery", &props(&[("label", "49%")]), t0 + Duration::seconds(5));
+
+        let item = cache.get("battery").unwrap();
+        assert_eq!(item.props["icon"], "A");
+        assert_eq!(item.props["label"], "49%");
+        assert_eq!(item.last_refresh, t0 + Duration::seconds(5));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let t0 = Local::now();
+        let mut cache = RenderCache::new();
+        cache.record("clock", &props(&[("label", "01/01 12:00")]), t0);
+
+        let snapshot = cache.snapshot(t0 + Duration::seconds(42));
+        assert_eq!(snapshot["clock"]["props"]["label"], "01/01 12:00");
+        assert_eq!(snapshot["clock"]["age_secs"], 42);
+        assert_eq!(snapshot["clock"]["last_refresh"], t0.to_rfc3339());
+    }
+
+    #[test]
+    fn test_replay_batch() {
+        let t0 = Local::now();
+        let mut cache = RenderCache::new();
+        cache.record("cpu", &props(&[("label", "3%")]), t0);
+        cache.record("battery", &props(&[("label", "50%"), ("icon", "A")]), t0);
+
+        assert_eq!(cache.replay_batch().args(), vec![
+            "--set", "battery", "icon=A", "label=50%",
+            "--set", "cpu", "label=3%",
+        ]);
+    }
+
+    #[test]
+    fn test_replay_adds_items_first() {
+        let mut cache = RenderCache::new();
+        let mut batch = SketchybarBatch::new();
+        batch
+            .add("workspace.web", "left")
+            .set("workspace.web", &[("label", "web")])
+            .add("workspace.mail", "left")
+            .reorder(&["workspace.web", "workspace.mail"])
+            .remove("workspace.mail");
+        cache.record_batch(&batch);
+
+        assert_eq!(cache.replay_batch().args(), vec![
+            "--add", "item", "workspace.web", "left",
+            "--set", "workspace.web", "label=web",
+            "--reorder", "workspace.web",
+        ]);
+    }
+}
@@ -0,0 +1,591 @@
+//! Periodic and one-shot jobs
+//!
+//! Every timed refresh (brew, clock, battery, system, teams, ...) is a named
+//! job owned by one `Scheduler`. Repeating jobs take their interval from the
+//! current `Config`, so a reload reschedules them; any job can be paused,
+//! resumed or triggered on demand. Time comes from a `Clock`, and jobs are
+//! handed to a spawn function, so tests drive the scheduler synchronously.
+//!
+//! While the machine or its display sleeps the scheduler is suspended and
+//! nothing runs; paused jobs stay paused across a suspend.
+//!
+//! Wall-clock aligned jobs (the clock item) work out their next run from the
+//! wall clock rather than an interval. The monotonic clock stops while the
+//! machine sleeps, so whenever the wall clock is found to have moved further
+//! than the monotonic one, they are rescheduled from the wall clock again.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::Value;
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::handlers::HandlerResult;
+
+type Task = Box<dyn FnMut(&Config) -> HandlerResult + Send>;
+
+/// How often a job runs
+#[derive(Debug, Clone, Copy)]
+pub enum Repeat {
+    /// Once, after its delay (and again whenever it is triggered)
+    Once,
+    /// Repeatedly, with the interval read from the config
+    Every(fn(&Config) -> Duration),
+    /// Repeatedly, waiting however long the function says from the given
+    /// wall-clock time
+    Aligned(fn(&Config, SystemTime) -> Duration),
+}
+
+/// Wall-clock drift beyond which aligned jobs are rescheduled
+const MAX_DRIFT: Duration = Duration::from_secs(1);
+
+struct Job {
+    name: &'static str,
+    repeat: Repeat,
+    /// When the job is due next; `None` for a one-shot job that already ran
+    next: Option<Instant>,
+    /// When the last run started
+    last_run: Option<Instant>,
+    paused: bool,
+    /// Run at the next chance, even if paused or not due
+    triggered: bool,
+    /// A run is in progress; the job isn't started again until it finishes
+    running: bool,
+    task: Arc<Mutex<Task>>,
+}
+
+impl Job {
+    /// When the job runs next if it (last) ran at `now`
+    fn next_after(&self, now: Instant, wall: SystemTime, config: &Config) -> Option<Instant> {
+        match self.repeat {
+            Repeat::Once => None,
+            Repeat::Every(interval) => Some(now + interval(config)),
+            Repeat::Aligned(until) => Some(now + until(config, wall)),
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        !self.running && (self.triggered || (!self.paused && self.next.is_some_and(|t| t <= now)))
+    }
+}
+
+struct Inner {
+    config: Config,
+    jobs: Vec<Job>,
+    /// Bumped whenever the schedule changes, so the runner re-plans
+    generation: u64,
+    /// Monotonic and wall-clock time when the jobs were last looked at
+    last_check: Option<(Instant, SystemTime)>,
+    /// Nothing runs while the machine (or its display) sleeps
+    suspended: bool,
+}
+
+impl Inner {
+    fn job(&mut self, name: &str) -> Result<&mut Job, String> {
+        self.jobs
+            .iter_mut()
+            .find(|j| j.name == name)
+            .ok_or_else(|| format!("Unknown job '{}'", name))
+    }
+}
+
+/// Owns every timed job and decides when each one runs
+pub struct Scheduler {
+    clock: Arc<dyn Clock>,
+    inner: Mutex<Inner>,
+    changed: Condvar,
+}
+
+impl std::fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.lock().jobs.iter().map(|j| j.name).collect();
+        f.debug_struct("Scheduler").field("jobs", &names).finish()
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: Config, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            inner: Mutex::new(Inner {
+                config,
+                jobs: Vec::new(),
+                generation: 0,
+                last_check: None,
+                suspended: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Add a job that first runs after `delay`
+    pub fn add(
+        &self,
+        name: &'static str,
+        repeat: Repeat,
+        delay: Duration,
+        task: impl FnMut(&Config) -> HandlerResult + Send + 'static,
+    ) {
+        let next = Some(self.clock.now() + delay);
+        self.update(|inner| {
+            inner.jobs.retain(|j| j.name != name);
+            inner.jobs.push(Job {
+                name,
+                repeat,
+                next,
+                last_run: None,
+                paused: false,
+                triggered: false,
+                running: false,
+                task: Arc::new(Mutex::new(Box::new(task))),
+            });
+        });
+    }
+
+    /// Use a new config; repeating jobs are rescheduled from their last run,
+    /// aligned ones from now
+    pub fn set_config(&self, config: Config) {
+        let (now, wall) = (self.clock.now(), self.clock.wall());
+        self.update(|inner| {
+            for job in &mut inner.jobs {
+                match (job.repeat, job.last_run) {
+                    (Repeat::Every(interval), Some(last_run)) => {
+                        job.next = Some(last_run + interval(&config));
+                    }
+                    (Repeat::Aligned(_), Some(_)) => job.next = job.next_after(now, wall, &config),
+                    _ => {}
+                }
+            }
+            inner.config = config;
+        });
+    }
+
+    /// Hold every job, paused or not, until `wake`
+    pub fn suspend(&self) {
+        self.update(|inner| inner.suspended = true);
+    }
+
+    /// Let jobs run again after `suspend`, with aligned jobs realigned. Jobs
+    /// that came due meanwhile run once.
+    pub fn wake(&self) {
+        let (now, wall) = (self.clock.now(), self.clock.wall());
+        self.update(|inner| {
+            inner.suspended = false;
+            realign(inner, now, wall);
+        });
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.lock().suspended
+    }
+
+    /// Trigger every repeating job (one-shot jobs are setup, not refreshes)
+    pub fn trigger_all(&self) {
+        self.update(|inner| {
+            for job in &mut inner.jobs {
+                if !matches!(job.repeat, Repeat::Once) {
+                    job.triggered = true;
+                }
+            }
+        });
+    }
+
+    /// Stop running `name` until it is resumed
+    pub fn pause(&self, name: &str) -> Result<(), String> {
+        self.try_update(|inner| {
+            inner.job(name)?.paused = true;
+            Ok(())
+        })
+    }
+
+    /// Let a paused job run again; if it came due meanwhile it runs right away
+    pub fn resume(&self, name: &str) -> Result<(), String> {
+        self.try_update(|inner| {
+            inner.job(name)?.paused = false;
+            Ok(())
+        })
+    }
+
+    /// Run `name` as soon as possible, whether it is due or paused. A
+    /// repeating job's next run is counted from this one.
+    pub fn trigger(&self, name: &str) -> Result<(), String> {
+        self.try_update(|inner| {
+            inner.job(name)?.triggered = true;
+            Ok(())
+        })
+    }
+
+    /// Start every job that is due, passing each run to `spawn`. Returns how
+    /// long until the next job is due (`None` if nothing is scheduled) and
+    /// the generation to pass to `wait`.
+    pub fn run_due(self: &Arc<Self>, spawn: impl Fn(Box<dyn FnOnce() + Send>)) -> (Option<Duration>, u64) {
+        let (now, wall) = (self.clock.now(), self.clock.wall());
+        let mut runs = Vec::new();
+        let (next, generation) = {
+            let mut inner = self.lock();
+            if inner.suspended {
+                return (None, inner.generation);
+            }
+            if let Some((then, then_wall)) = inner.last_check {
+                let elapsed = now.saturating_duration_since(then);
+                let wall_elapsed = wall.duration_since(then_wall).unwrap_or(Duration::MAX);
+                if wall_elapsed.abs_diff(elapsed) > MAX_DRIFT {
+                    realign(&mut inner, now, wall);
+                }
+            }
+            inner.last_check = Some((now, wall));
+
+            let config = inner.config.clone();
+            for job in inner.jobs.iter_mut().filter(|j| j.is_due(now)) {
+                job.running = true;
+                job.triggered = false;
+                job.last_run = Some(now);
+                job.next = job.next_after(now, wall, &config);
+                runs.push((job.name, Arc::clone(&job.task)));
+            }
+            let next = inner
+                .jobs
+                .iter()
+                .filter(|j| !j.paused)
+                // An overdue job that is still running wakes us when it finishes
+                .filter_map(|j| j.next.filter(|t| !j.running || *t > now))
+                .min()
+                .map(|t| t.saturating_duration_since(now));
+            (next, inner.generation)
+        };
+
+        for (name, task) in runs {
+            let scheduler = Arc::clone(self);
+            spawn(Box::new(move || {
+                let config = scheduler.lock().config.clone();
+                let result = (task.lock().unwrap_or_else(|e| e.into_inner()))(&config);
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+                scheduler.update(|inner| {
+                    if let Ok(job) = inner.job(name) {
+                        job.running = false;
+                    }
+                });
+            }));
+        }
+        (next, generation)
+    }
+
+    /// Sleep for `timeout` (forever if `None`) unless the schedule changes
+    /// after `generation` was read
+    pub fn wait(&self, generation: u64, timeout: Option<Duration>) {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut inner = self.lock();
+        while inner.generation == generation {
+            inner = match deadline {
+                None => self.changed.wait(inner).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    self.changed
+                        .wait_timeout(inner, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+
+    /// Run jobs on background threads as they come due, forever
+    pub fn start(self: &Arc<Self>) {
+        let scheduler = Arc::clone(self);
+        thread::spawn(move || loop {
+            let (next, generation) = scheduler.run_due(|run| {
+                thread::spawn(run);
+            });
+            scheduler.wait(generation, next);
+        });
+    }
+
+    /// JSON view of every job, used by `dump-state`
+    pub fn snapshot(&self) -> Value {
+        let now = self.clock.now();
+        let inner = self.lock();
+        inner
+            .jobs
+            .iter()
+            .map(|j| {
+                serde_json::json!({
+                    "name": j.name,
+                    "paused": j.paused,
+                    "running": j.running,
+                    "next_run_in_ms": j.next.map(|t| t.saturating_duration_since(now).as_millis() as u64),
+                })
+            })
+            .collect()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Inner)) {
+        let mut inner = self.lock();
+        f(&mut inner);
+        inner.generation += 1;
+        self.changed.notify_all();
+    }
+
+    fn try_update(&self, f: impl FnOnce(&mut Inner) -> Result<(), String>) -> Result<(), String> {
+        let mut result = Ok(());
+        self.update(|inner| result = f(inner));
+        result
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Reschedule the aligned jobs that have run (the others wait for their delay)
+fn realign(inner: &mut Inner, now: Instant, wall: SystemTime) {
+    let config = inner.config.clone();
+    for job in &mut inner.jobs {
+        if matches!(job.repeat, Repeat::Aligned(_)) && job.last_run.is_some() {
+            job.next = job.next_after(now, wall, &config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    /// A scheduler, its clock and a run counter for a job called "job"
+    fn setup(repeat: Repeat, delay: Duration) -> (Arc<Scheduler>, Arc<FakeClock>, Arc<AtomicUsize>) {
+        let clock = Arc::new(FakeClock::new());
+        let scheduler = Arc::new(Scheduler::new(Config::default(), clock.clone()));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        scheduler.add("job", repeat, delay, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        (scheduler, clock, runs)
+    }
+
+    /// Run due jobs on this thread
+    fn step(scheduler: &Arc<Scheduler>) -> Option<Duration> {
+        scheduler.run_due(|run| run()).0
+    }
+
+    fn battery(config: &Config) -> Duration {
+        secs(config.intervals.battery)
+    }
+
+    #[test]
+    fn test_repeating_job_follows_config_interval() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        assert_eq!(step(&scheduler), Some(secs(120)));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        clock.advance(secs(100));
+        assert_eq!(step(&scheduler), Some(secs(20)));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        clock.advance(secs(20));
+        assert_eq!(step(&scheduler), Some(secs(120)));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_one_shot_job_runs_once_after_delay() {
+        let (scheduler, clock, runs) = setup(Repeat::Once, secs(1));
+        assert_eq!(step(&scheduler), Some(secs(1)));
+        clock.advance(secs(1));
+        assert_eq!(step(&scheduler), None);
+        clock.advance(secs(60));
+        assert_eq!(step(&scheduler), None);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        scheduler.trigger("job").unwrap();
+        step(&scheduler);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        step(&scheduler);
+        scheduler.pause("job").unwrap();
+        clock.advance(secs(300));
+        assert_eq!(step(&scheduler), None);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Overdue while paused: runs once on resume, then on the interval
+        scheduler.resume("job").unwrap();
+        assert_eq!(step(&scheduler), Some(secs(120)));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_trigger_runs_paused_job_and_restarts_interval() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        step(&scheduler);
+        scheduler.pause("job").unwrap();
+        clock.advance(secs(50));
+        scheduler.trigger("job").unwrap();
+        assert_eq!(step(&scheduler), None);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        scheduler.resume("job").unwrap();
+        assert_eq!(step(&scheduler), Some(secs(120)));
+        assert!(scheduler.trigger("missing").is_err());
+    }
+
+    #[test]
+    fn test_new_config_reschedules_from_last_run() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        step(&scheduler);
+        clock.advance(secs(30));
+
+        let mut config = Config::default();
+        config.intervals.battery = 40;
+        scheduler.set_config(config);
+        assert_eq!(step(&scheduler), Some(secs(10)));
+        clock.advance(secs(10));
+        step(&scheduler);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_running_job_is_not_started_again() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        let pending = Mutex::new(Vec::new());
+        scheduler.run_due(|run| pending.lock().unwrap().push(run));
+        clock.advance(secs(500));
+        scheduler.run_due(|run| pending.lock().unwrap().push(run));
+        assert_eq!(pending.lock().unwrap().len(), 1);
+
+        for run in pending.into_inner().unwrap() {
+            run();
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        step(&scheduler);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    fn minute(_: &Config, wall: SystemTime) -> Duration {
+        crate::clock::until_next_tick(wall, false, secs(3600))
+    }
+
+    #[test]
+    fn test_aligned_job_runs_on_the_minute() {
+        // The fake clock starts 20 s into a minute
+        let (scheduler, clock, runs) = setup(Repeat::Aligned(minute), Duration::ZERO);
+        assert_eq!(step(&scheduler), Some(Duration::from_millis(40_050)));
+        clock.advance(Duration::from_millis(40_050));
+        assert_eq!(step(&scheduler), Some(secs(60)));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_aligned_job_realigns_after_sleep() {
+        let (scheduler, clock, runs) = setup(Repeat::Aligned(minute), Duration::ZERO);
+        step(&scheduler);
+        clock.advance(secs(10));
+        step(&scheduler);
+
+        // Asleep for 10 minutes and 5 seconds: the monotonic clock didn't move,
+        // so the old deadline would be 30 s late
+        clock.suspend(secs(605));
+        assert_eq!(step(&scheduler), Some(Duration::from_millis(25_050)));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // A wake event realigns explicitly and gives the same schedule
+        scheduler.wake();
+        assert_eq!(step(&scheduler), Some(Duration::from_millis(25_050)));
+    }
+
+    #[test]
+    fn test_suspend_holds_every_job_until_wake() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        step(&scheduler);
+        scheduler.pause("job").unwrap();
+        scheduler.suspend();
+        scheduler.trigger("job").unwrap();
+        clock.advance(secs(600));
+        assert_eq!(step(&scheduler), None);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Waking keeps the job paused, but the trigger still runs it once
+        scheduler.wake();
+        assert!(!scheduler.is_suspended());
+        assert_eq!(step(&scheduler), None);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_suspend_holds_the_config_watcher() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        let checks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&checks);
+        scheduler.add("config-watch", Repeat::Every(|_| secs(2)), secs(2), move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        step(&scheduler);
+        scheduler.suspend();
+        for _ in 0..10 {
+            clock.advance(secs(2));
+            assert_eq!(step(&scheduler), None);
+        }
+        assert_eq!(checks.load(Ordering::SeqCst), 0);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        scheduler.wake();
+        clock.advance(secs(2));
+        step(&scheduler);
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_trigger_all_skips_one_shot_jobs() {
+        let (scheduler, clock, runs) = setup(Repeat::Every(battery), Duration::ZERO);
+        let once = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&once);
+        scheduler.add("setup", Repeat::Once, Duration::ZERO, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        step(&scheduler);
+        clock.advance(secs(10));
+
+        scheduler.trigger_all();
+        assert_eq!(step(&scheduler), Some(secs(120)));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(once.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_wait_returns_early_on_change() {
+        let (scheduler, _, _) = setup(Repeat::Once, Duration::ZERO);
+        let (_, generation) = scheduler.run_due(|_| {});
+        let other = Arc::clone(&scheduler);
+        let handle = thread::spawn(move || other.trigger("job").unwrap());
+
+        let start = Instant::now();
+        scheduler.wait(generation, Some(secs(10)));
+        assert!(start.elapsed() < secs(10));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_times_out_without_change() {
+        let (scheduler, _, _) = setup(Repeat::Once, Duration::ZERO);
+        let (_, generation) = scheduler.run_due(|_| {});
+        scheduler.wait(generation, Some(Duration::from_millis(5)));
+    }
+}
@@ -0,0 +1,314 @@
+//! Output sinks that `SketchybarBatch` delivers its commands to
+//!
+//! On macOS the daemon normally talks to sketchybar over its mach port, but it
+//! can also shell out to the `sketchybar` binary, print commands for a dry run,
+//! or (in tests) record them in memory. Elsewhere there is no mach port and the
+//! `sketchybar` binary is used instead.
+
+use std::fmt;
+use std::io::Write;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "macos")]
+use crate::mach_client;
+pub use crate::reconnect::Delivery;
+
+/// Destination for batched sketchybar arguments
+pub trait Sink: Send + Sync {
+    /// Deliver one batch of sketchybar arguments (e.g. `["--set", "clock", "label=12:00"]`).
+    ///
+    /// Returns `Delivery::Reconnected` when sketchybar had to be reconnected and
+    /// has probably lost the state pushed before.
+    fn send(&self, args: &[String]) -> Result<Delivery, String>;
+}
+
+/// Which sink the daemon sends to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SinkKind {
+    /// Sketchybar's mach port (fastest, macOS only)
+    #[cfg_attr(target_os = "macos", default)]
+    Mach,
+    /// The `sketchybar` command line tool
+    #[cfg_attr(not(target_os = "macos"), default)]
+    Cli,
+    /// Print commands to stdout instead of sending them (dry run)
+    Stdout,
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mach" => Ok(SinkKind::Mach),
+            "cli" => Ok(SinkKind::Cli),
+            "stdout" | "dry-run" => Ok(SinkKind::Stdout),
+            _ => Err(format!("Unknown sink '{}' (expected mach, cli or stdout)", s)),
+        }
+    }
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SinkKind::Mach => "mach",
+            SinkKind::Cli => "cli",
+            SinkKind::Stdout => "stdout",
+        };
+        f.write_str(name)
+    }
+}
+
+impl SinkKind {
+    pub fn build(self) -> Arc<dyn Sink> {
+        match self {
+            #[cfg(target_os = "macos")]
+            SinkKind::Mach => Arc::new(MachSink),
+            #[cfg(not(target_os = "macos"))]
+            SinkKind::Mach => {
+                eprintln!("The mach sink needs macOS, using the sketchybar command line tool");
+                Arc::new(CliSink::default())
+            }
+            SinkKind::Cli => Arc::new(CliSink::default()),
+            SinkKind::Stdout => Arc::new(StdoutSink),
+        }
+    }
+}
+
+/// Encode arguments in sketchybar's wire format: each argument followed by a
+/// NUL byte. Arguments are passed through verbatim (no quoting or splitting),
+/// so values may contain spaces, quotes, `=` and newlines. NUL bytes can't be
+/// represented and are dropped.
+#[cfg(any(target_os = "macos", test))]
+pub fn encode_args(args: &[String]) -> Vec<u8> {
+    let len = args.iter().map(|a| a.len() + 1).sum::<usize>().max(1);
+    let mut encoded = Vec::with_capacity(len);
+    for arg in args {
+        encoded.extend(arg.bytes().filter(|&b| b != 0));
+        encoded.push(0);
+    }
+    if encoded.is_empty() {
+        encoded.push(0);
+    }
+    encoded
+}
+
+/// Quote an argument for display as part of a shell command line
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.,:=/+%@[]".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Sends commands through sketchybar's mach port
+#[cfg(target_os = "macos")]
+#[derive(Debug, Default)]
+pub struct MachSink;
+
+#[cfg(target_os = "macos")]
+impl Sink for MachSink {
+    fn send(&self, args: &[String]) -> Result<Delivery, String> {
+        mach_client::sketchybar(&encode_args(args))
+            .map_err(|e| format!("sketchybar mach command failed: {}", e))
+    }
+}
+
+/// Runs the `sketchybar` binary with the batch as its arguments
+#[derive(Debug)]
+pub struct CliSink {
+    binary: String,
+}
+
+impl Default for CliSink {
+    fn default() -> Self {
+        Self {
+            binary: "sketchybar".to_string(),
+        }
+    }
+}
+
+impl Sink for CliSink {
+    fn send(&self, args: &[String]) -> Result<Delivery, String> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", self.binary, e))?;
+
+        if output.status.success() {
+            Ok(Delivery::Sent)
+        } else {
+            Err(format!(
+                "{} exited with {}: {}",
+                self.binary,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+/// Prints each batch as a `sketchybar` command line instead of sending it
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn send(&self, args: &[String]) -> Result<Delivery, String> {
+        let mut stdout = std::io::stdout().lock();
+        let line: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+        writeln!(stdout, "sketchybar {}", line.join(" "))
+            .map(|_| Delivery::Sent)
+            .map_err(|e| format!("Failed to write to stdout: {}", e))
+    }
+}
+
+/// Keeps every batch in memory so tests can assert on it
+#[allow(dead_code)] // Used in tests
+#[derive(Debug, Default)]
+pub struct RecordingSink {
+    batches: Mutex<Vec<Vec<String>>>,
+}
+
+#[allow(dead_code)] // Used in tests
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every batch received so far, in order
+    pub fn batches(&self) -> Vec<Vec<String>> {
+        self.batches.lock().unwrap().clone()
+    }
+
+    /// All received arguments flattened into one list
+    pub fn args(&self) -> Vec<String> {
+        self.batches().into_iter().flatten().collect()
+    }
+}
+
+impl Sink for RecordingSink {
+    fn send(&self, args: &[String]) -> Result<Delivery, String> {
+        self.batches.lock().unwrap().push(args.to_vec());
+        Ok(Delivery::Sent)
+    }
+}
+
+/// The sink used by `SketchybarBatch::execute` (the default kind until one is
+/// installed)
+static SINK: RwLock<Option<Arc<dyn Sink>>> = RwLock::new(None);
+
+/// Route all future batches to `sink`
+pub fn install(sink: Arc<dyn Sink>) {
+    if let Ok(mut current) = SINK.write() {
+        *current = Some(sink);
+    }
+}
+
+/// The sink batches are currently sent to
+pub fn current() -> Arc<dyn Sink> {
+    SINK.read()
+        .ok()
+        .and_then(|current| current.clone())
+        .unwrap_or_else(|| SinkKind::default().build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::SketchybarBatch;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_sink_kind_parse() {
+        assert_eq!("mach".parse::<SinkKind>(), Ok(SinkKind::Mach));
+        assert_eq!("cli".parse::<SinkKind>(), Ok(SinkKind::Cli));
+        assert_eq!("dry-run".parse::<SinkKind>(), Ok(SinkKind::Stdout));
+        assert!("pigeon".parse::<SinkKind>().is_err());
+    }
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Split an encoded message back into its arguments, the way sketchybar does
+    fn decode_args(message: &[u8]) -> Vec<String> {
+        let body = message.strip_suffix(&[0]).unwrap_or(message);
+        body.split(|&b| b == 0)
+            .map(|a| String::from_utf8(a.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_args() {
+        let args = strings(&["--set", "item", "label=hello world"]);
+        assert_eq!(encode_args(&args), b"--set\0item\0label=hello world\0");
+    }
+
+    #[test]
+    fn test_encode_args_keeps_quotes_and_equals() {
+        let args = strings(&["--set", "front_app", "label=› Bob's \"Burgers\" a=b\nc"]);
+        assert_eq!(decode_args(&encode_args(&args)), args);
+    }
+
+    #[test]
+    fn test_encode_args_empty_value_and_nul() {
+        let args = strings(&["--set", "teams", "label=", "icon=a\0b"]);
+        assert_eq!(
+            decode_args(&encode_args(&args)),
+            strings(&["--set", "teams", "label=", "icon=ab"])
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("label=50%"), "label=50%");
+        assert_eq!(shell_quote("label=› Safari"), "'label=› Safari'");
+        assert_eq!(shell_quote("label=Bob's"), "'label=Bob'\\''s'");
+        assert_eq!(shell_quote("icon="), "icon=");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    proptest! {
+        #[test]
+        fn prop_values_arrive_intact(item in "[a-z_.0-9]{1,16}", value in "\\PC*") {
+            let mut batch = SketchybarBatch::new();
+            batch.set(&item, &[("label", &value)]);
+
+            let decoded = decode_args(&encode_args(&batch.args()));
+            prop_assert_eq!(decoded.len(), 3);
+            prop_assert_eq!(&decoded[1], &item);
+            let (key, sent) = decoded[2].split_once('=').unwrap();
+            prop_assert_eq!(key, "label");
+            prop_assert_eq!(sent, value.replace('\0', ""));
+        }
+
+        #[test]
+        fn prop_args_round_trip(args in proptest::collection::vec("[^\\x00]*", 0..8)) {
+            let decoded = decode_args(&encode_args(&args));
+            if args.is_empty() {
+                prop_assert_eq!(decoded, vec![String::new()]);
+            } else {
+                prop_assert_eq!(decoded, args);
+            }
+        }
+    }
+
+    #[test]
+    fn test_recording_sink() {
+        let sink = RecordingSink::new();
+        sink.send(&["--set".to_string(), "clock".to_string()]).unwrap();
+        sink.send(&["--animate".to_string()]).unwrap();
+        assert_eq!(sink.batches().len(), 2);
+        assert_eq!(sink.args(), vec!["--set", "clock", "--animate"]);
+    }
+}
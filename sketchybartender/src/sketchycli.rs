@@ -1,9 +1,22 @@
 //! Lightweight CLI tool that forwards messages to the daemon via socket
+//!
+//! Waits for the daemon's reply, prints any payload to stdout and exits with:
+//!   0 - the command succeeded
+//!   1 - the command failed or the daemon could not be reached
+//!   2 - the daemon does not know the command
+
+mod protocol;
 
 use std::env;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use protocol::Response;
+
+/// How long to wait for the daemon to answer before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn get_socket_path() -> PathBuf {
     let cache_dir = env::var("XDG_CACHE_HOME")
@@ -16,6 +29,26 @@ fn get_socket_path() -> PathBuf {
     cache_dir.join("sketchybar").join("helper.sock")
 }
 
+/// Send one request line and wait for the daemon's reply line
+fn send_request(stream: UnixStream, message: &str) -> Result<Response, String> {
+    let mut writer = &stream;
+    writeln!(writer, "{}", message).map_err(|e| format!("Failed to send message: {}", e))?;
+
+    stream
+        .set_read_timeout(Some(REPLY_TIMEOUT))
+        .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+
+    let mut line = String::new();
+    let read = BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read reply: {}", e))?;
+    if read == 0 {
+        return Err("Daemon closed the connection without replying".to_string());
+    }
+
+    Response::from_line(&line)
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
@@ -29,17 +62,32 @@ fn main() {
 
     // Forward to daemon
     let socket_path = get_socket_path();
-    match UnixStream::connect(&socket_path) {
-        Ok(mut stream) => {
-            if let Err(e) = writeln!(stream, "{}", message) {
-                eprintln!("Failed to send message: {}", e);
-                std::process::exit(1);
-            }
-        }
+    let stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
         Err(e) => {
             eprintln!("Failed to connect to daemon at {:?}: {}", socket_path, e);
             eprintln!("Is sketchybartender daemon running?");
             std::process::exit(1);
         }
+    };
+
+    let response = match send_request(stream, &message) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match &response {
+        Response::Ok { payload: Some(payload) } => {
+            let pretty = serde_json::to_string_pretty(payload).unwrap_or_else(|_| payload.to_string());
+            println!("{}", pretty);
+        }
+        Response::Ok { payload: None } => {}
+        Response::Error { message } => eprintln!("Error: {}", message),
+        Response::UnknownCommand { command } => eprintln!("Unknown command: {}", command),
     }
+
+    std::process::exit(response.exit_code());
 }